@@ -0,0 +1,257 @@
+use crate::editor::grid_layout;
+
+/// Name, usage line and help text for a shell builtin.
+/// This table is the single list of builtins used by `help` and completion.
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub description: &'static str,
+}
+
+pub static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "cd",
+        usage: "cd [path]",
+        summary: "Change directory",
+        description: "Change the working directory to PATH, or to $HOME when no path is given.",
+    },
+    Builtin {
+        name: "pwd",
+        usage: "pwd",
+        summary: "Print working directory",
+        description: "Print the absolute path of the current working directory.",
+    },
+    Builtin {
+        name: "ls",
+        usage: "ls [-la] [path...]",
+        summary: "List directory contents",
+        description: "List each PATH that is a file, then the entries of each PATH that is a directory \
+                      (default: the current directory), colored by file type. \
+                      -l shows permissions, size and modification time; -a includes dotfiles.",
+    },
+    Builtin {
+        name: "cat",
+        usage: "cat <file>...",
+        summary: "Display file contents",
+        description: "Print the contents of each FILE in turn to standard output.",
+    },
+    Builtin {
+        name: "mkdir",
+        usage: "mkdir [-p] <dir>...",
+        summary: "Create directory",
+        description: "Create each directory DIR. The parent directory must already exist, unless \
+                      -p is given: it creates missing parents and allows DIR to exist already.",
+    },
+    Builtin {
+        name: "rm",
+        usage: "rm [-rf] <file>...",
+        summary: "Remove file",
+        description: "Remove each FILE. Directories need -r, which removes them together with \
+                      their contents. -f ignores files that don't exist.",
+    },
+    Builtin {
+        name: "touch",
+        usage: "touch <file>",
+        summary: "Create empty file",
+        description: "Create FILE, truncating it if it already exists.",
+    },
+    Builtin {
+        name: "echo",
+        usage: "echo [-neE] [args...]",
+        summary: "Print arguments",
+        description: "Print the arguments separated by spaces, followed by a newline. -n leaves \
+                      out the newline; -e interprets escapes like \\t, \\x41 and \\c (stop output) \
+                      even in single quotes, and -E turns that off again.",
+    },
+    Builtin {
+        name: "env",
+        usage: "env [-i] [cmd]",
+        summary: "Run a command in a modified environment",
+        description: "Run CMD with NAME=value assignments added to the environment, or print the \
+                      environment when no command is given. -i starts from an empty environment.",
+    },
+    Builtin {
+        name: "export",
+        usage: "export [NAME[=value]...]",
+        summary: "Set environment variables",
+        description: "Set each NAME to VALUE. Variables live in the shell's environment, so \
+                      NAME=value works the same way. With no arguments, list the variables.",
+    },
+    Builtin {
+        name: "clear",
+        usage: "clear",
+        summary: "Clear screen",
+        description: "Clear the terminal screen.",
+    },
+    Builtin {
+        name: "sleep",
+        usage: "sleep <n[smh]>",
+        summary: "Pause for a duration",
+        description: "Pause for N seconds, or minutes/hours with an m/h suffix. Fractions are \
+                      allowed and several durations are added together. Ctrl-C interrupts.",
+    },
+    Builtin {
+        name: "history",
+        usage: "history",
+        summary: "Show command history",
+        description: "List previously entered commands with their history numbers. With \
+                      HISTTIMEFORMAT set, each shows when it was entered, in that strftime format.",
+    },
+    Builtin {
+        name: "jobs",
+        usage: "jobs [-lp]",
+        summary: "List background jobs",
+        description: "List background jobs with their status and process id. -l also shows \
+                      each job's process group; -p prints only the process ids.",
+    },
+    Builtin {
+        name: "fg",
+        usage: "fg [job_id]",
+        summary: "Bring job to foreground",
+        description: "Wait for job JOB_ID (default 1) in the foreground.",
+    },
+    Builtin {
+        name: "bg",
+        usage: "bg [job_id]",
+        summary: "Resume job in background",
+        description: "Let job JOB_ID (default 1) continue running in the background.",
+    },
+    Builtin {
+        name: "disown",
+        usage: "disown [job_id]",
+        summary: "Stop tracking a job",
+        description: "Remove job JOB_ID (default: the most recent job) from the job table \
+                      without killing it. Its completion is no longer reported.",
+    },
+    Builtin {
+        name: "kill",
+        usage: "kill [-signal] %job|pid",
+        summary: "Send a signal to a job or process",
+        description: "Send SIGNAL (default TERM) to job %JOB or process PID. SIGNAL is a name \
+                      like TERM or SIGKILL, or a number.",
+    },
+    Builtin {
+        name: "chpwd",
+        usage: "chpwd [-r] [cmd]",
+        summary: "Run a command after cd",
+        description: "Run CMD every time cd changes the working directory. With no arguments, \
+                      print the current hook; -r removes it.",
+    },
+    Builtin {
+        name: "source",
+        usage: "source <file>",
+        summary: "Run commands from a file",
+        description: "Run each line of FILE in the current shell. Errors are reported with \
+                      their line number and the remaining lines still run. `.` is an alias.",
+    },
+    Builtin {
+        name: "set",
+        usage: "set [-o|+o] [option]",
+        summary: "Set shell options",
+        description: "Turn OPTION on with -o or off with +o, or list the options. Options: \
+                      emacs and vi pick the line editing keys, pipefail makes a pipeline fail when any \
+                      of its commands fails, expand_aliases expands aliases (off in scripts and -c), \
+                      huponexit sends SIGHUP to running jobs on exit, debugparse prints each parsed \
+                      command.",
+    },
+    Builtin {
+        name: "alias",
+        usage: "alias [name[=value]...]",
+        summary: "Define or list aliases",
+        description: "Make NAME run VALUE when it is the first word of a command, as in \
+                      alias ll='ls -l'. With a NAME alone, print that alias; with no arguments, \
+                      list them all. An alias may use a name it defines, like alias ls='ls -F'.",
+    },
+    Builtin {
+        name: "unalias",
+        usage: "unalias [-a] name...",
+        summary: "Remove aliases",
+        description: "Remove each alias NAME, or every alias with -a.",
+    },
+    Builtin {
+        name: "type",
+        usage: "type name...",
+        summary: "Describe a command name",
+        description: "Tell whether each NAME is an alias, a shell builtin or a program, and \
+                      which file the program is. Unknown names are an error.",
+    },
+    Builtin {
+        name: "time",
+        usage: "time [command]",
+        summary: "Time a command",
+        description: "Run COMMAND, which may be a pipeline, then print the elapsed real time and \
+                      the user and system CPU time it used to stderr.",
+    },
+    Builtin {
+        name: "reload",
+        usage: "reload",
+        summary: "Re-run ~/.rshellrc",
+        description: "Run ~/.rshellrc again in the current shell, like `source ~/.rshellrc`. \
+                      Ctrl-X Ctrl-R at the prompt does the same.",
+    },
+    Builtin {
+        name: "help",
+        usage: "help [-s] [builtin]",
+        summary: "Show help",
+        description: "List the builtins, or show detailed help for BUILTIN. -s prints every \
+                      builtin with its summary, sorted by name, in columns.",
+    },
+    Builtin {
+        name: "exit",
+        usage: "exit",
+        summary: "Exit shell",
+        description: "Exit the shell.",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Every builtin as `name  summary`, sorted by name and laid out in columns
+/// filling `width`, top to bottom then left to right. Returns the lines.
+pub fn index(width: usize) -> Vec<String> {
+    let mut builtins: Vec<&Builtin> = BUILTINS.iter().collect();
+    builtins.sort_by_key(|b| b.name);
+
+    let name_width = builtins.iter().map(|b| b.name.len()).max().unwrap_or(0);
+    let cells: Vec<String> = builtins
+        .iter()
+        .map(|b| format!("{:<name_width$}  {}", b.name, b.summary))
+        .collect();
+    let cell_width = cells.iter().map(|c| c.chars().count()).max().unwrap_or(0) + 2;
+    // The last column needs no gap after it.
+    let (cols, rows) = grid_layout(cells.len(), cell_width, width + 2);
+
+    (0..rows)
+        .map(|row| {
+            let line: String = (0..cols)
+                .filter_map(|col| cells.get(col * rows + row))
+                .map(|cell| format!("{:<cell_width$}", cell))
+                .collect();
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_fits_width() {
+        let mut names: Vec<&str> = BUILTINS.iter().map(|b| b.name).collect();
+        names.sort();
+
+        let lines = index(200);
+        assert!(lines.len() < BUILTINS.len());
+        assert!(lines.iter().all(|line| line.chars().count() <= 200));
+        assert!(lines[0].starts_with(&format!("{} ", names[0])));
+
+        // Too narrow for two columns, so one builtin per line in name order.
+        let narrow = index(20);
+        let narrow_names: Vec<&str> = narrow.iter().filter_map(|line| line.split_whitespace().next()).collect();
+        assert_eq!(narrow_names, names);
+    }
+}
@@ -1,26 +1,83 @@
-use crate::jobs::JobManager;
+use crate::builtins;
+use crate::glob;
+use crate::jobs::{self, ForegroundOutcome, JobManager};
+use crate::signal_handler;
+use crate::variables;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::process::{Command as ProcessCommand, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum Command {
     Cd(Option<String>),
     Pwd,
-    Echo(Vec<String>),
+    /// `echo [-neE] [args...]`. `-n` leaves out the newline and `-e` interprets
+    /// backslash escapes left in the arguments, like those in single quotes;
+    /// outside single quotes the parser has already turned `\n`, `\t` and
+    /// `\r` into the characters.
+    Echo { args: Vec<String>, newline: bool, escapes: bool },
     Exit,
-    Help,
-    Ls(Option<String>),
-    Cat(String),
-    Mkdir(String),
-    Rm(String),
+    Help(Option<String>),
+    /// `help -s`: every builtin with its summary, in columns.
+    HelpIndex,
+    /// `ls [-la] [path...]`: `long` prints permissions, size and mtime, `all`
+    /// includes dotfiles. Files are listed as given, then the entries of each
+    /// directory.
+    Ls { paths: Vec<String>, long: bool, all: bool },
+    /// `cat file...`: prints each file in turn.
+    Cat(Vec<String>),
+    /// `mkdir [-p] dir...`: `parents` creates missing parents and accepts
+    /// directories that already exist. As in GNU mkdir, `-p` may also follow
+    /// the directories, and `--` ends the options.
+    Mkdir { paths: Vec<String>, parents: bool },
+    /// `rm [-rf] target...`: directories need `recursive`, `force` ignores
+    /// missing targets.
+    Rm { targets: Vec<String>, recursive: bool, force: bool },
     Touch(String),
     Clear,
+    Sleep(Duration),
     History,
-    Jobs,
-    Fg(u32),
-    Bg(u32),
+    /// `jobs [-lp]`: `-l` adds each job's process group, `-p` prints only
+    /// the process ids.
+    Jobs { long: bool, pids: bool },
+    /// `fg [job]`; the job spec is resolved by the shell, default the current job.
+    Fg(Option<String>),
+    Bg(Option<String>),
+    /// `disown [job_id]`; the most recent job when no id is given.
+    Disown(Option<u32>),
+    /// `kill [-signal] %job|pid`; the signal defaults to SIGTERM.
+    Kill {
+        target: String,
+        signal: Option<String>,
+    },
+    Chpwd(Vec<String>),
+    Source(String),
+    /// Re-run `~/.rshellrc`.
+    Reload,
+    /// `set -o` / `set -o NAME` / `set +o NAME`.
+    Set(Vec<String>),
+    /// `alias [name[=value]...]`; a name without a value prints that alias.
+    Alias(Vec<(String, Option<String>)>),
+    /// `unalias [-a] name...`; `-a` removes every alias.
+    Unalias(Vec<String>),
+    /// `type name...`: whether each name is an alias, a builtin or a program.
+    Type(Vec<String>),
+    /// `time command`: run the rest of the line and report how long it took.
+    Time(String),
+    /// `NAME=value ...` on its own sets variables in the shell's environment.
+    Assign(Vec<(String, String)>),
+    /// `export [NAME[=value]...]`; a name without a value is left as it is.
+    Export(Vec<(String, Option<String>)>),
+    Env {
+        clear: bool,
+        assignments: Vec<(String, String)>,
+        command: Vec<String>,
+    },
     External {
         program: String,
         args: Vec<String>,
@@ -28,20 +85,394 @@ pub enum Command {
     },
 }
 
+/// Expand a leading `~` or `~user` to that user's home directory.
+/// A `~` anywhere else in the argument is left alone.
+pub fn expand_tilde(arg: &str) -> String {
+    let rest = match arg.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return arg.to_string(),
+    };
+    let (user, tail) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, ""),
+    };
+
+    let home = if user.is_empty() {
+        env::var("HOME").ok()
+    } else {
+        user_home(user)
+    };
+
+    match home {
+        Some(home) => format!("{}{}", home, tail),
+        None => arg.to_string(),
+    }
+}
+
+/// Quote `word` so it reads back as a single word when pasted into the shell.
+/// Words without special characters are left alone. A leading `~` is quoted
+/// so it isn't expanded again.
+pub fn quote_for_display(word: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "_-./:=@%+,~".contains(c);
+    if !word.is_empty() && !word.starts_with('~') && word.chars().all(plain) {
+        word.to_string()
+    } else if !word.contains('\'') {
+        format!("'{}'", word)
+    } else {
+        let mut quoted = String::from("\"");
+        for c in word.chars() {
+            if matches!(c, '"' | '\\' | '$' | '`') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+/// Join words into a command line, quoting each as needed for display.
+pub fn join_for_display<S: AsRef<str>>(words: &[S]) -> String {
+    words
+        .iter()
+        .map(|word| quote_for_display(word.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Full path of the executable `name` would run, searching `$PATH`.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        #[cfg(unix)]
+        Ok(meta) => {
+            use std::os::unix::fs::PermissionsExt;
+            meta.is_file() && meta.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        Ok(meta) => meta.is_file(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<String> {
+    nix::unistd::User::from_name(user)
+        .ok()
+        .flatten()
+        .map(|u| u.dir.to_string_lossy().to_string())
+}
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<String> {
+    None
+}
+
+/// Whether to emit ANSI colors: stdout is a terminal and `NO_COLOR` is unset
+/// or empty. All colored output goes by this, the prompt included.
+pub fn color_enabled() -> bool {
+    color_allowed(env::var_os("NO_COLOR"), io::stdout().is_terminal())
+}
+
+/// `color_enabled` for a given `NO_COLOR` value and terminal check.
+fn color_allowed(no_color: Option<OsString>, is_tty: bool) -> bool {
+    no_color.is_none_or(|v| v.is_empty()) && is_tty
+}
+
+/// The file types `ls` distinguishes when coloring its output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Directory,
+    Symlink,
+    BrokenSymlink,
+    Executable,
+    Regular,
+}
+
+impl FileKind {
+    fn of(path: &Path) -> Self {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => return FileKind::Regular,
+        };
+
+        if meta.file_type().is_symlink() {
+            return if path.exists() { FileKind::Symlink } else { FileKind::BrokenSymlink };
+        }
+        if meta.is_dir() {
+            return FileKind::Directory;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if meta.permissions().mode() & 0o111 != 0 {
+                return FileKind::Executable;
+            }
+        }
+
+        FileKind::Regular
+    }
+
+    /// SGR color code for this kind, taken from `LS_COLORS` when it sets one.
+    fn ls_color(self) -> Option<String> {
+        let (key, default) = match self {
+            FileKind::Directory => ("di", "34"),
+            FileKind::Symlink => ("ln", "36"),
+            FileKind::BrokenSymlink => ("or", "31"),
+            FileKind::Executable => ("ex", "32"),
+            FileKind::Regular => return None,
+        };
+
+        let from_env = env::var("LS_COLORS").ok().and_then(|spec| {
+            spec.split(':')
+                .filter_map(|entry| entry.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, code)| code.to_string())
+        });
+        Some(from_env.unwrap_or_else(|| default.to_string()))
+    }
+}
+
+/// Write `items` (name, kind, path) sorted by name, four to a line, or one
+/// per line with mode, size and modification time when `long`.
+fn write_ls_items(
+    out: &mut dyn Write,
+    mut items: Vec<(String, FileKind, PathBuf)>,
+    long: bool,
+    color: bool,
+) -> io::Result<()> {
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if long {
+        // Entries removed since the directory was read are skipped.
+        let rows: Vec<_> = items
+            .iter()
+            .filter_map(|(name, kind, path)| Some((name, kind, path, fs::symlink_metadata(path).ok()?)))
+            .collect();
+        let size_width = rows.iter().map(|row| row.3.len().to_string().len()).max().unwrap_or(0);
+        for (name, kind, path, meta) in rows {
+            let name = match kind.ls_color().filter(|_| color) {
+                Some(code) => format!("\x1b[{}m{}\x1b[0m", code, name),
+                None => name.clone(),
+            };
+            let target = match fs::read_link(path) {
+                Ok(target) if meta.file_type().is_symlink() => format!(" -> {}", target.display()),
+                _ => String::new(),
+            };
+            let mtime = meta.modified().map(format_mtime).unwrap_or_else(|_| format!("{:12}", "?"));
+            writeln!(out, "{} {:>size_width$} {} {}{}", format_mode(&meta), meta.len(), mtime, name, target)?;
+        }
+        return Ok(());
+    }
+
+    for (i, (name, kind, _)) in items.iter().enumerate() {
+        match kind.ls_color().filter(|_| color) {
+            Some(code) => write!(out, "\x1b[{}m{:<20}\x1b[0m", code, name)?,
+            None => write!(out, "{:<20}", name)?,
+        }
+
+        if (i + 1) % 4 == 0 {
+            writeln!(out)?;
+        }
+    }
+    writeln!(out)
+}
+
+/// File type and permissions as `ls -l` shows them, e.g. `drwxr-xr-x`.
+fn format_mode(meta: &fs::Metadata) -> String {
+    let file_type = meta.file_type();
+    let kind = if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_dir() {
+        'd'
+    } else {
+        '-'
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = meta.permissions().mode();
+        let bits: String = (0..9)
+            .map(|i| if mode & (0o400 >> i) == 0 { '-' } else { ['r', 'w', 'x'][i % 3] })
+            .collect();
+        format!("{}{}", kind, bits)
+    }
+    #[cfg(not(unix))]
+    {
+        format!("{}{}", kind, if meta.permissions().readonly() { "r--r--r--" } else { "rw-rw-rw-" })
+    }
+}
+
+/// Modification time as `ls -l` shows it, `Oct 16 13:46` in local time, or
+/// `Oct 16  2025` when it is more than about six months away from now.
+fn format_mtime(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+    let recent = (now - secs).abs() < 182 * 24 * 60 * 60;
+
+    #[cfg(unix)]
+    {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let t = secs as libc::time_t;
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return format!("{:12}", "?");
+        }
+        let month = MONTHS[tm.tm_mon.clamp(0, 11) as usize];
+        if recent {
+            format!("{} {:>2} {:02}:{:02}", month, tm.tm_mday, tm.tm_hour, tm.tm_min)
+        } else {
+            format!("{} {:>2} {:>5}", month, tm.tm_mday, tm.tm_year + 1900)
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (MONTHS, recent);
+        format!("{:>12}", secs)
+    }
+}
+
+/// Interpret the backslash escapes `echo -e` knows in `text`: `\a \b \e \f
+/// \n \r \t \v \\`, `\0nnn` in octal and `\xHH` in hex. Others are kept as
+/// they are. The result is bytes, since `\xff` and the like are single bytes
+/// rather than characters. `\c` ends the output; the flag returned says it
+/// was seen, so no newline follows either.
+fn echo_escapes(text: &str) -> (Vec<u8>, bool) {
+    let mut result = Vec::new();
+    let push = |result: &mut Vec<u8>, c: char| result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push(&mut result, c);
+            continue;
+        }
+        let (radix, max_digits) = match chars.peek() {
+            Some('0') => (8, 3),
+            Some('x') => (16, 2),
+            _ => (0, 0),
+        };
+        if radix != 0 {
+            chars.next();
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_digit(radix) && digits.len() < max_digits) {
+                digits.push(d);
+                chars.next();
+            }
+            // Like bash, `\0777` wraps around to a single byte.
+            match u32::from_str_radix(&digits, radix) {
+                Ok(byte) => result.push(byte as u8),
+                // `\x` with no digits stays as written; `\0` alone is NUL.
+                Err(_) if radix == 16 => result.extend_from_slice(b"\\x"),
+                Err(_) => result.push(0),
+            }
+            continue;
+        }
+        match chars.next() {
+            Some('a') => result.push(0x07),
+            Some('b') => result.push(0x08),
+            Some('c') => return (result, true),
+            Some('e') => result.push(0x1b),
+            Some('f') => result.push(0x0c),
+            Some('n') => result.push(b'\n'),
+            Some('r') => result.push(b'\r'),
+            Some('t') => result.push(b'\t'),
+            Some('v') => result.push(0x0b),
+            Some('\\') => result.push(b'\\'),
+            Some(other) => {
+                result.push(b'\\');
+                push(&mut result, other);
+            }
+            None => result.push(b'\\'),
+        }
+    }
+
+    (result, false)
+}
+
+/// What a piece of a command line is, as `Command::tokenize` reads it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// Unquoted text of a word, `$(...)` included.
+    Word,
+    /// A quoted or backticked part of a word, quotes included.
+    Quoted,
+    /// A pipe, `&&`, `||`, `&` or redirect between words.
+    Operator,
+}
+
+/// Operators `tokenize` recognizes, longer ones first so `>>` isn't read as `>`.
+const OPERATORS: &[&str] = &[
+    "&&", "||", "|&", "|", "&>", "2>&1", "2>>", "2>", ">>", ">&", ">", "<<<", "<<", "<", "&",
+];
+
+/// The quotes and parentheses open at some point of a line being read left
+/// to right, so the readers of a line agree on which parts are quoted.
+#[derive(Default)]
+struct QuoteState {
+    quote: Option<char>,
+    depth: usize,
+}
+
+impl QuoteState {
+    /// Outside quotes, backticks and parentheses, where operators take effect.
+    fn top_level(&self) -> bool {
+        self.quote.is_none() && self.depth == 0
+    }
+
+    /// Step over the char at the start of `rest`, together with the char
+    /// after it when it is a backslash outside single quotes. Returns the
+    /// number of bytes stepped over.
+    fn advance(&mut self, rest: &str) -> usize {
+        let mut chars = rest.chars();
+        let Some(c) = chars.next() else {
+            return 0;
+        };
+        match c {
+            '\\' if self.quote != Some('\'') => return c.len_utf8() + chars.next().map_or(0, char::len_utf8),
+            '\'' | '"' | '`' if self.quote.is_none() => self.quote = Some(c),
+            c if self.quote == Some(c) => self.quote = None,
+            '(' if self.quote.is_none() => self.depth += 1,
+            ')' if self.quote.is_none() => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        c.len_utf8()
+    }
+}
+
 impl Command {
-    pub fn parse(input: &str) -> Option<Self> {
+    /// `time` with the rest of `input` kept as it is, so pipes and redirects
+    /// in it belong to the timed command. `None` unless `input` starts with
+    /// the word `time`.
+    pub fn parse_time(input: &str) -> Option<Self> {
+        let rest = input.trim().strip_prefix("time")?;
+        (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| Command::Time(rest.trim().to_string()))
+    }
+
+    /// Parse one line into a command. `Ok(None)` means there was nothing to run;
+    /// `Err` carries a message describing the syntax or usage error.
+    pub fn parse(input: &str) -> Result<Option<Self>, String> {
         let input = input.trim();
         if input.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        let input = match Self::expand_subshells(input) {
-            Ok(expanded) => expanded,
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                return None;
-            }
-        };
+        if let Some(time) = Self::parse_time(input) {
+            return Ok(Some(time));
+        }
+
+        let input = Self::expand_substitutions(input).map_err(|e| format!("Error: {}", e))?;
 
         let background = input.ends_with('&');
         let input = if background {
@@ -50,80 +481,295 @@ impl Command {
             input.as_str()
         };
 
-        let parts = Self::parse_args(input);
+        let mut words = Self::parse_words(input).0;
+        for (word, quoted) in words.iter_mut().skip(1) {
+            if !*quoted {
+                *word = expand_tilde(word);
+            }
+        }
+        let parts: Vec<String> = words.iter().map(|(word, _)| word.clone()).collect();
 
         if parts.is_empty() {
-            return None;
+            return Ok(None);
         }
 
         if parts.len() == 1 && (parts[0] == "\\" || parts[0].is_empty()) {
-            return None;
+            return Ok(None);
+        }
+
+        // Leading NAME=value words. Values were expanded without splitting and aren't globbed.
+        let assignment_count = words
+            .iter()
+            .take_while(|(word, _)| variables::split_assignment(word).is_some())
+            .count();
+        if assignment_count > 0 {
+            let assignments: Vec<(String, String)> = words[..assignment_count]
+                .iter()
+                .map(|(word, quoted)| Self::assignment(word, *quoted))
+                .collect();
+            if assignment_count == words.len() {
+                return Ok(Some(Command::Assign(assignments)));
+            }
+            return Ok(Some(Command::Env {
+                clear: false,
+                assignments,
+                command: Self::glob_words(&words[assignment_count..]),
+            }));
         }
 
         let cmd = &parts[0];
         let args: Vec<String> = parts[1..].to_vec();
+        let glob_args = || Self::glob_words(&words[1..]);
 
-        match cmd.as_str() {
-            "cd" => Some(Command::Cd(args.first().cloned())),
-            "pwd" => Some(Command::Pwd),
-            "echo" => Some(Command::Echo(args)),
-            "exit" => Some(Command::Exit),
-            "help" => Some(Command::Help),
-            "ls" => Some(Command::Ls(args.first().cloned())),
+        let cmd = match cmd.as_str() {
+            "cd" => Command::Cd(args.first().cloned()),
+            "pwd" => Command::Pwd,
+            "echo" => {
+                let (mut newline, mut escapes) = (true, false);
+                // Like bash, only words made up of known flags are options.
+                let options = args.iter().take_while(|arg| {
+                    arg.strip_prefix('-')
+                        .is_some_and(|flags| !flags.is_empty() && flags.chars().all(|c| "neE".contains(c)))
+                });
+                for flag in options.clone().flat_map(|arg| arg[1..].chars()) {
+                    match flag {
+                        'n' => newline = false,
+                        'e' => escapes = true,
+                        _ => escapes = false,
+                    }
+                }
+                let args = args[options.count()..].to_vec();
+                Command::Echo { args, newline, escapes }
+            }
+            "exit" => Command::Exit,
+            "help" => match args.first().map(String::as_str) {
+                Some("-s") => Command::HelpIndex,
+                _ => Command::Help(args.first().cloned()),
+            },
+            "ls" => {
+                let (mut long, mut all) = (false, false);
+                let mut paths = Vec::new();
+                for arg in glob_args() {
+                    match arg.strip_prefix('-').filter(|flags| !flags.is_empty() && paths.is_empty()) {
+                        Some(flags) => {
+                            for flag in flags.chars() {
+                                match flag {
+                                    'l' => long = true,
+                                    'a' => all = true,
+                                    _ => return Err(format!("ls: invalid option -- '{}'", flag)),
+                                }
+                            }
+                        }
+                        None => paths.push(arg),
+                    }
+                }
+                Command::Ls { paths, long, all }
+            }
             "cat" => {
                 if args.is_empty() {
-                    eprintln!("cat: missing file operand");
-                    None
-                } else {
-                    Some(Command::Cat(args[0].clone()))
+                    return Err("cat: missing file operand".to_string());
                 }
+                Command::Cat(glob_args())
             }
             "mkdir" => {
-                if args.is_empty() {
-                    eprintln!("mkdir: missing operand");
-                    None
-                } else {
-                    Some(Command::Mkdir(args[0].clone()))
+                let mut parents = false;
+                let mut paths = Vec::new();
+                let mut options_ended = false;
+                for arg in args {
+                    match arg.strip_prefix('-').filter(|flags| !flags.is_empty() && !options_ended) {
+                        Some("-") => options_ended = true,
+                        Some(flags) => {
+                            for flag in flags.chars() {
+                                match flag {
+                                    'p' => parents = true,
+                                    _ => return Err(format!("mkdir: invalid option -- '{}'", flag)),
+                                }
+                            }
+                        }
+                        None => paths.push(arg.clone()),
+                    }
+                }
+                if paths.is_empty() {
+                    return Err("mkdir: missing operand".to_string());
                 }
+                Command::Mkdir { paths, parents }
             }
             "rm" => {
-                if args.is_empty() {
-                    eprintln!("rm: missing operand");
-                    None
-                } else {
-                    Some(Command::Rm(args[0].clone()))
+                let (mut recursive, mut force) = (false, false);
+                let mut targets = Vec::new();
+                for arg in glob_args() {
+                    match arg.strip_prefix('-').filter(|flags| !flags.is_empty() && targets.is_empty()) {
+                        Some(flags) => {
+                            for flag in flags.chars() {
+                                match flag {
+                                    'r' | 'R' => recursive = true,
+                                    'f' => force = true,
+                                    _ => return Err(format!("rm: invalid option -- '{}'", flag)),
+                                }
+                            }
+                        }
+                        None => targets.push(arg),
+                    }
+                }
+                if targets.is_empty() && !force {
+                    return Err("rm: missing operand".to_string());
                 }
+                Command::Rm { targets, recursive, force }
             }
             "touch" => {
                 if args.is_empty() {
-                    eprintln!("touch: missing file operand");
-                    None
-                } else {
-                    Some(Command::Touch(args[0].clone()))
+                    return Err("touch: missing file operand".to_string());
+                }
+                Command::Touch(args[0].clone())
+            }
+            "clear" => Command::Clear,
+            "sleep" => {
+                if args.is_empty() {
+                    return Err("sleep: missing operand".to_string());
+                }
+                let mut total = Duration::ZERO;
+                for arg in &args {
+                    match Self::parse_duration(arg).and_then(|duration| total.checked_add(duration)) {
+                        Some(sum) => total = sum,
+                        None => return Err(format!("sleep: invalid time interval '{}'", arg)),
+                    }
+                }
+                Command::Sleep(total)
+            }
+            "history" => Command::History,
+            "jobs" => {
+                let (mut long, mut pids) = (false, false);
+                for arg in &args {
+                    let flags = arg.strip_prefix('-').filter(|flags| !flags.is_empty());
+                    for flag in flags.ok_or_else(|| format!("jobs: {}: no such job", arg))?.chars() {
+                        match flag {
+                            'l' => long = true,
+                            'p' => pids = true,
+                            _ => return Err(format!("jobs: invalid option -- '{}'", flag)),
+                        }
+                    }
+                }
+                Command::Jobs { long, pids }
+            }
+            "fg" => Command::Fg(args.first().cloned()),
+            "bg" => Command::Bg(args.first().cloned()),
+            "disown" => match args.first() {
+                Some(arg) => match arg.trim_start_matches('%').parse() {
+                    Ok(job_id) => Command::Disown(Some(job_id)),
+                    Err(_) => return Err(format!("disown: {}: no such job", arg)),
+                },
+                None => Command::Disown(None),
+            },
+            "kill" => match args.as_slice() {
+                [signal, target] if signal.starts_with('-') => Command::Kill {
+                    target: target.clone(),
+                    signal: Some(signal[1..].to_string()),
+                },
+                [target] if !target.starts_with('-') => Command::Kill {
+                    target: target.clone(),
+                    signal: None,
+                },
+                _ => return Err("kill: usage: kill [-signal] %job | pid".to_string()),
+            },
+            "chpwd" => Command::Chpwd(args),
+            "export" => {
+                let mut vars = Vec::new();
+                for (word, quoted) in &words[1..] {
+                    if variables::split_assignment(word).is_some() {
+                        let (name, value) = Self::assignment(word, *quoted);
+                        vars.push((name, Some(value)));
+                    } else if variables::is_valid_name(word) {
+                        vars.push((word.clone(), None));
+                    } else {
+                        return Err(format!("export: `{}': not a valid identifier", word));
+                    }
                 }
+                Command::Export(vars)
             }
-            "clear" => Some(Command::Clear),
-            "history" => Some(Command::History),
-            "jobs" => Some(Command::Jobs),
-            "fg" => {
-                let job_id = args.first().and_then(|s| s.parse().ok()).unwrap_or(1);
-                Some(Command::Fg(job_id))
+            "source" | "." => match args.first() {
+                Some(path) => Command::Source(path.clone()),
+                None => return Err(format!("{}: filename argument required", cmd)),
+            },
+            "reload" => Command::Reload,
+            "set" => Command::Set(args),
+            "alias" => Command::Alias(
+                args.iter()
+                    .map(|arg| match arg.split_once('=') {
+                        Some((name, value)) if !name.is_empty() => (name.to_string(), Some(value.to_string())),
+                        _ => (arg.clone(), None),
+                    })
+                    .collect(),
+            ),
+            "unalias" => {
+                if args.is_empty() {
+                    return Err("unalias: usage: unalias [-a] name [name ...]".to_string());
+                }
+                Command::Unalias(args)
             }
-            "bg" => {
-                let job_id = args.first().and_then(|s| s.parse().ok()).unwrap_or(1);
-                Some(Command::Bg(job_id))
+            "type" => Command::Type(args),
+            "env" => {
+                let mut clear = false;
+                let mut rest = args.as_slice();
+                while let Some(flag) = rest.first() {
+                    match flag.as_str() {
+                        "-i" | "-" | "--ignore-environment" => clear = true,
+                        _ => break,
+                    }
+                    rest = &rest[1..];
+                }
+
+                let mut assignments = Vec::new();
+                while let Some((name, value)) = rest.first().and_then(|a| a.split_once('=')) {
+                    if name.is_empty() {
+                        break;
+                    }
+                    assignments.push((name.to_string(), value.to_string()));
+                    rest = &rest[1..];
+                }
+
+                Command::Env {
+                    clear,
+                    assignments,
+                    command: rest.to_vec(),
+                }
             }
-            _ => Some(Command::External {
+            _ => Command::External {
                 program: cmd.clone(),
-                args,
+                args: glob_args(),
                 background,
-            }),
+            },
+        };
+        Ok(Some(cmd))
+    }
+
+    /// Split a `NAME=value` word, expanding a leading `~` in an unquoted value.
+    fn assignment(word: &str, quoted: bool) -> (String, String) {
+        let (name, value) = variables::split_assignment(word).unwrap_or((word, ""));
+        let value = if quoted { value.to_string() } else { expand_tilde(value) };
+        (name.to_string(), value)
+    }
+
+    /// Build the child process for `env`, starting from an empty environment when `clear` is set.
+    fn env_command(clear: bool, assignments: &[(String, String)], command: &[String]) -> ProcessCommand {
+        let mut cmd = ProcessCommand::new(&command[0]);
+        cmd.args(&command[1..]);
+        if clear {
+            cmd.env_clear();
         }
+        cmd.envs(assignments.iter().map(|(name, value)| (name, value)));
+        cmd
     }
 
     pub fn parse_args_with_state(input: &str) -> (Vec<String>, bool) {
+        let (words, in_quotes) = Self::parse_words(input);
+        (words.into_iter().map(|(word, _)| word).collect(), in_quotes)
+    }
+
+    /// Split input into words, recording whether any part of each word was quoted or escaped.
+    fn parse_words(input: &str) -> (Vec<(String, bool)>, bool) {
         let mut args = Vec::new();
         let mut current_arg = String::new();
+        let mut current_quoted = false;
         let mut in_quotes = false;
         let mut quote_char = ' ';
         let mut chars = input.chars().peekable();
@@ -131,6 +777,11 @@ impl Command {
 
         while let Some(c) = chars.next() {
             if escape_next {
+                escape_next = false;
+                // Backslash-newline joins the lines.
+                if c == '\n' {
+                    continue;
+                }
                 current_arg.push(match c {
                     'n' => '\n',
                     't' => '\t',
@@ -140,17 +791,19 @@ impl Command {
                     '\'' => '\'',
                     _ => c,
                 });
-                escape_next = false;
                 continue;
             }
 
             match c {
-                '\\' if in_quotes || chars.peek().is_some() => {
+                // Backslashes are literal inside single quotes.
+                '\\' if quote_char != '\'' && (in_quotes || chars.peek().is_some()) => {
                     escape_next = true;
+                    current_quoted = true;
                 }
                 '"' | '\'' if !in_quotes => {
                     in_quotes = true;
                     quote_char = c;
+                    current_quoted = true;
                 }
                 '"' | '\'' if in_quotes && c == quote_char => {
                     in_quotes = false;
@@ -161,80 +814,372 @@ impl Command {
                 }
                 ' ' if !in_quotes => {
                     if !current_arg.is_empty() {
-                        args.push(current_arg.clone());
+                        args.push((current_arg.clone(), current_quoted));
                         current_arg.clear();
                     }
+                    current_quoted = false;
                 }
                 _ => current_arg.push(c),
             }
         }
 
         if !current_arg.is_empty() {
-            args.push(current_arg);
+            args.push((current_arg, current_quoted));
         }
 
         (args, in_quotes)
     }
 
+    /// Expand one word of a redirected command or pipeline stage as `parse`
+    /// expands a line: substitutions and variables, then `~` and globs
+    /// outside quotes. An unquoted expansion can split it into several words.
+    pub fn expand_word(word: &str) -> Result<Vec<String>, String> {
+        let expanded = Self::expand_substitutions(word)?;
+        let words: Vec<(String, bool)> = Self::parse_words(&expanded)
+            .0
+            .into_iter()
+            .map(|(word, quoted)| if quoted { (word, quoted) } else { (expand_tilde(&word), quoted) })
+            .collect();
+        Ok(Self::glob_words(&words))
+    }
+
+    /// Glob-expand the unquoted words; quoted words are passed through literally.
+    fn glob_words(words: &[(String, bool)]) -> Vec<String> {
+        let mut args = Vec::new();
+        for (word, quoted) in words {
+            if *quoted {
+                args.push(word.clone());
+            } else {
+                args.extend(glob::expand_globs(vec![word.clone()]));
+            }
+        }
+        args
+    }
+
     fn parse_args(input: &str) -> Vec<String> {
         Self::parse_args_with_state(input).0
     }
 
+    /// Parse a `sleep` operand: a non-negative number with an optional `s`, `m` or `h` suffix.
+    fn parse_duration(arg: &str) -> Option<Duration> {
+        let (number, multiplier) = match arg.char_indices().last()? {
+            (i, 's') => (&arg[..i], 1.0),
+            (i, 'm') => (&arg[..i], 60.0),
+            (i, 'h') => (&arg[..i], 3600.0),
+            _ => (arg, 1.0),
+        };
+        let value: f64 = number.parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        Duration::try_from_secs_f64(value * multiplier).ok()
+    }
+
+    /// Split `input` at each of `separators` found outside quotes, backticks and
+    /// parentheses, so `$(a | b)` and `'a && b'` stay whole. Each piece comes
+    /// with the separator that preceded it. Separators are tried in order.
+    pub fn split_unquoted<'a>(input: &str, separators: &[&'a str]) -> Vec<(Option<&'a str>, String)> {
+        let mut pieces = Vec::new();
+        let mut current = String::new();
+        let mut before = None;
+        let mut state = QuoteState::default();
+        let mut rest = input;
+
+        while !rest.is_empty() {
+            if state.top_level() {
+                if let Some(separator) = separators.iter().find(|sep| rest.starts_with(**sep)) {
+                    pieces.push((before, std::mem::take(&mut current)));
+                    before = Some(*separator);
+                    rest = &rest[separator.len()..];
+                    continue;
+                }
+            }
+
+            let len = state.advance(rest);
+            current.push_str(&rest[..len]);
+            rest = &rest[len..];
+        }
+        pieces.push((before, current));
+        pieces
+    }
+
+    /// Split `input` into the byte ranges of its unquoted text, quoted text
+    /// and operators, reading quotes the way `split_unquoted` does. Quotes are
+    /// part of the quoted text, and an unclosed quote runs to the end. Blanks
+    /// between words are left out; the pieces of one word touch.
+    pub fn tokenize(input: &str) -> Vec<(TokenKind, Range<usize>)> {
+        let mut tokens: Vec<(TokenKind, Range<usize>)> = Vec::new();
+        let mut state = QuoteState::default();
+        let mut pos = 0;
+
+        while let Some(c) = input[pos..].chars().next() {
+            let rest = &input[pos..];
+            if state.top_level() {
+                if c.is_whitespace() {
+                    pos += c.len_utf8();
+                    continue;
+                }
+                // `2>` only redirects at the start of a word; `a2>f` writes `a2` to f.
+                let in_word = matches!(tokens.last(), Some((kind, range)) if *kind != TokenKind::Operator && range.end == pos);
+                let operator = OPERATORS
+                    .iter()
+                    .find(|op| rest.starts_with(**op) && !(in_word && op.starts_with(|c: char| c.is_ascii_digit())));
+                if let Some(operator) = operator {
+                    tokens.push((TokenKind::Operator, pos..pos + operator.len()));
+                    pos += operator.len();
+                    continue;
+                }
+            }
+
+            let was_quoted = state.quote.is_some();
+            let len = state.advance(rest);
+            let kind = if was_quoted || state.quote.is_some() { TokenKind::Quoted } else { TokenKind::Word };
+            match tokens.last_mut() {
+                Some((last, range)) if *last == kind && range.end == pos => range.end += len,
+                _ => tokens.push((kind, pos..pos + len)),
+            }
+            pos += len;
+        }
+        tokens
+    }
+
+    /// True while a quote is open or the input ends in an unescaped backslash.
     pub fn needs_line_continuation(input: &str) -> bool {
         let (_, in_quotes) = Self::parse_args_with_state(input);
-        in_quotes
+        let trailing_backslashes = input.chars().rev().take_while(|&c| c == '\\').count();
+        in_quotes || trailing_backslashes % 2 == 1
     }
 
-    fn expand_subshells(input: &str) -> Result<String, String> {
+    /// Remove backslash-newline pairs, joining continued lines like bash does.
+    /// Inside single quotes the backslash and newline are both kept.
+    pub fn join_line_continuations(input: &str) -> String {
+        let mut result = String::new();
+        let mut chars = input.chars();
+        let mut quote: Option<char> = None;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if quote != Some('\'') => match chars.next() {
+                    Some('\n') => {}
+                    Some(next) => {
+                        result.push(c);
+                        result.push(next);
+                    }
+                    None => result.push(c),
+                },
+                '"' | '\'' if quote.is_none() => {
+                    quote = Some(c);
+                    result.push(c);
+                }
+                '"' | '\'' if quote == Some(c) => {
+                    quote = None;
+                    result.push(c);
+                }
+                _ => result.push(c),
+            }
+        }
+
+        result
+    }
+
+    /// Run command substitutions and expand `$NAME`/`${NAME}` outside single quotes.
+    /// Expanded text is escaped so its quotes stay literal, and within an
+    /// assignment word (`NAME=...`) it isn't split on spaces or globbed.
+    fn expand_substitutions(input: &str) -> Result<String, String> {
         let mut result = String::new();
         let mut chars = input.chars().peekable();
-        let mut depth = 0;
-        let mut subshell = String::new();
+        let mut quote: Option<char> = None;
+        let mut word_start = true;
+        let mut in_assignment = false;
 
         while let Some(c) = chars.next() {
+            if word_start && c != ' ' {
+                in_assignment = Self::starts_assignment(c, &chars);
+            }
+            word_start = quote.is_none() && c == ' ';
+
             match c {
-                '(' => {
-                    depth += 1;
-                    if depth > 1 {
-                        subshell.push(c);
+                '\\' if quote != Some('\'') => {
+                    result.push(c);
+                    if let Some(next) = chars.next() {
+                        result.push(next);
                     }
                 }
-                ')' => {
-                    depth -= 1;
-                    if depth == 0 {
-                        let output = Self::execute_subshell(&subshell)?;
-                        result.push_str(&output);
-                        subshell.clear();
-                    } else if depth > 0 {
-                        subshell.push(c);
-                    } else {
-                        return Err("Unmatched closing parenthesis".to_string());
+                '"' | '\'' if quote.is_none() => {
+                    quote = Some(c);
+                    result.push(c);
+                }
+                '"' | '\'' if quote == Some(c) => {
+                    quote = None;
+                    result.push(c);
+                }
+                '$' if quote != Some('\'') && chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let subshell = Self::take_subshell(&mut chars)?;
+                    let output = Self::execute_subshell(&subshell)?;
+                    result.push_str(&variables::escape_value(&output, quote, !in_assignment));
+                }
+                '$' if quote != Some('\'') => match variables::take_name(&mut chars) {
+                    Some(name) => {
+                        let value = variables::lookup(&name);
+                        result.push_str(&variables::escape_value(&value, quote, !in_assignment));
                     }
+                    None => result.push(c),
+                },
+                '`' if quote != Some('\'') => {
+                    let subshell = Self::take_backtick(&mut chars)?;
+                    let output = Self::execute_subshell(&subshell)?;
+                    result.push_str(&variables::escape_value(&output, quote, !in_assignment));
                 }
-                _ => {
-                    if depth > 0 {
-                        subshell.push(c);
-                    } else {
-                        result.push(c);
+                '(' if quote.is_none() => {
+                    let subshell = Self::take_subshell(&mut chars)?;
+                    let output = Self::execute_subshell(&subshell)?;
+                    result.push_str(&variables::escape_value(&output, quote, !in_assignment));
+                }
+                ')' if quote.is_none() => {
+                    return Err("Unmatched closing parenthesis".to_string());
+                }
+                _ => result.push(c),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Whether the word starting with `first` (followed by `rest`) is `NAME=...`.
+    fn starts_assignment(first: char, rest: &std::iter::Peekable<std::str::Chars>) -> bool {
+        let mut name = first.to_string();
+        let mut lookahead = rest.clone();
+        while let Some(&c) = lookahead.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                name.push(c);
+                lookahead.next();
+            } else {
+                break;
+            }
+        }
+        lookahead.peek() == Some(&'=') && variables::is_valid_name(&name)
+    }
+
+    /// Run the `$(...)` and backtick substitutions in `text`, treating quotes as
+    /// plain characters. Used for text that isn't split into words, like `PS1`.
+    pub fn substitute_commands(text: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    result.push(c);
+                    if let Some(next) = chars.next() {
+                        result.push(next);
                     }
                 }
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let subshell = Self::take_subshell(&mut chars)?;
+                    result.push_str(&Self::execute_subshell(&subshell)?);
+                }
+                '`' => {
+                    let subshell = Self::take_backtick(&mut chars)?;
+                    result.push_str(&Self::execute_subshell(&subshell)?);
+                }
+                _ => result.push(c),
             }
         }
 
-        if depth > 0 {
-            return Err("Unmatched opening parenthesis".to_string());
+        Ok(result)
+    }
+
+    /// Expand `$NAME`, `${NAME}`, `$(...)` and backticks in `text` without
+    /// word splitting or quote removal, as for a here-string. A backslash
+    /// keeps a following `$`, backtick or backslash literal.
+    pub fn expand_text(text: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some('$' | '`' | '\\')) => {
+                    result.extend(chars.next());
+                }
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let subshell = Self::take_subshell(&mut chars)?;
+                    result.push_str(&Self::execute_subshell(&subshell)?);
+                }
+                '$' => match variables::take_name(&mut chars) {
+                    Some(name) => result.push_str(&variables::lookup(&name)),
+                    None => result.push(c),
+                },
+                '`' => {
+                    let subshell = Self::take_backtick(&mut chars)?;
+                    result.push_str(&Self::execute_subshell(&subshell)?);
+                }
+                _ => result.push(c),
+            }
         }
 
         Ok(result)
     }
 
+    /// Collect the body of a subshell up to its matching `)`, which is consumed.
+    /// Nested parentheses and quoted parentheses are kept in the body.
+    fn take_subshell(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+        let mut subshell = String::new();
+        let mut depth = 1;
+        let mut quote: Option<char> = None;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if quote != Some('\'') => {
+                    subshell.push(c);
+                    if let Some(next) = chars.next() {
+                        subshell.push(next);
+                    }
+                    continue;
+                }
+                '"' | '\'' if quote.is_none() => quote = Some(c),
+                '"' | '\'' if quote == Some(c) => quote = None,
+                '(' if quote.is_none() => depth += 1,
+                ')' if quote.is_none() => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(subshell);
+                    }
+                }
+                _ => {}
+            }
+            subshell.push(c);
+        }
+
+        Err("Unmatched opening parenthesis".to_string())
+    }
+
+    /// Collect the body of a backtick substitution up to the closing backtick, which is consumed.
+    fn take_backtick(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+        let mut subshell = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some('`') | Some('\\') | Some('$')) => {
+                    subshell.push(chars.next().unwrap());
+                }
+                '`' => return Ok(subshell),
+                _ => subshell.push(c),
+            }
+        }
+
+        Err("Unmatched backtick".to_string())
+    }
+
     fn execute_subshell(cmd: &str) -> Result<String, String> {
-        let cmd = cmd.trim();
+        let cmd = Self::expand_substitutions(cmd.trim())?;
         if cmd.is_empty() {
             return Ok(String::new());
         }
 
-        let args = Self::parse_args(cmd);
+        let args = Self::parse_args(&cmd);
         if args.is_empty() {
             return Ok(String::new());
         }
@@ -255,128 +1200,268 @@ impl Command {
             ));
         }
 
-        let mut result = String::from_utf8_lossy(&output.stdout).to_string();
-        if result.ends_with('\n') {
+        Ok(Self::substitution_output(&output.stdout))
+    }
+
+    /// Turn captured output into the text of a substitution. Arguments are
+    /// strings, so bytes that aren't valid UTF-8 become U+FFFD. Trailing line
+    /// endings are removed, both `\n` and `\r\n`.
+    fn substitution_output(stdout: &[u8]) -> String {
+        let mut result = String::from_utf8_lossy(stdout).into_owned();
+        while result.ends_with('\n') {
             result.pop();
+            if result.ends_with('\r') {
+                result.pop();
+            }
         }
+        result
+    }
 
-        Ok(result)
+    /// Run a builtin or external command and return its exit status: 0 on
+    /// success, 1 when a builtin fails. Builtins that need the shell's own
+    /// state are run by the shell instead.
+    pub fn execute(&self, job_manager: &mut JobManager) -> i32 {
+        self.execute_to(&mut io::stdout(), color_enabled(), job_manager)
     }
 
-    pub fn execute(&self, job_manager: &mut JobManager) -> bool {
+    /// Like [`Command::execute`], but builtins write their output to `out`,
+    /// e.g. to feed it to the next stage of a pipeline. `ls` colors its
+    /// output only with `color`. External commands still use the real stdout.
+    pub fn execute_to(&self, out: &mut dyn Write, color: bool, job_manager: &mut JobManager) -> i32 {
+        match self.run_builtin(out, color, job_manager) {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+
+    fn run_builtin(&self, out: &mut dyn Write, color: bool, job_manager: &mut JobManager) -> io::Result<i32> {
         match self {
-            Command::Cd(path) => {
-                let home = env::var("HOME").unwrap_or_else(|_| "/".to_string());
-                let target = path.as_deref().unwrap_or(&home);
+            Command::Pwd => {
+                if let Ok(path) = env::current_dir() {
+                    writeln!(out, "{}", path.display())?;
+                }
+            }
 
-                if let Err(e) = env::set_current_dir(target) {
-                    eprintln!("cd: {}", e);
+            Command::Echo { args, newline, escapes } => {
+                let text = args.join(" ");
+                let mut newline = *newline;
+                if *escapes {
+                    let (bytes, stop) = echo_escapes(&text);
+                    out.write_all(&bytes)?;
+                    newline &= !stop;
+                } else {
+                    write!(out, "{}", text)?;
+                }
+                if newline {
+                    writeln!(out)?;
                 }
             }
 
-            Command::Pwd => {
-                if let Ok(path) = env::current_dir() {
-                    println!("{}", path.display());
-                }
-            }
-
-            Command::Echo(args) => {
-                println!("{}", args.join(" "));
-            }
-
-            Command::Exit => {
-                return false;
-            }
-
-            Command::Help => {
-                println!("Available commands:");
-                println!("  cd [path]       - Change directory");
-                println!("  pwd             - Print working directory");
-                println!("  ls [path]       - List directory contents");
-                println!("  cat <file>      - Display file contents");
-                println!("  mkdir <dir>     - Create directory");
-                println!("  rm <file>       - Remove file");
-                println!("  touch <file>    - Create empty file");
-                println!("  echo [args...]  - Print arguments");
-                println!("  clear           - Clear screen");
-                println!("  history         - Show command history");
-                println!("  jobs            - List background jobs");
-                println!("  fg [job_id]     - Bring job to foreground");
-                println!("  bg [job_id]     - Resume job in background");
-                println!("  exit            - Exit shell");
-                println!("\nFeatures:");
-                println!("  - Quotes: echo \"hello world\" or echo 'single quotes'");
-                println!("  - Subshells: echo $(pwd) or echo $(ls)");
-                println!("  - Background: command &");
-                println!("  - Pipes: command1 | command2");
-                println!("  - Redirects: cmd < in > out >> append 2> err");
-                println!("  - Heredoc: cmd << EOF");
-            }
-
-            Command::Ls(path) => {
-                let target = path.as_deref().unwrap_or(".");
-                match fs::read_dir(target) {
-                    Ok(entries) => {
-                        let mut items: Vec<_> = entries
-                            .flatten()
-                            .map(|entry| {
-                                let name = entry.file_name().to_string_lossy().to_string();
-                                let is_dir = entry.path().is_dir();
-                                (name, is_dir)
-                            })
-                            .filter(|(name, _)| !name.starts_with('.'))
-                            .collect();
-
-                        items.sort_by(|a, b| a.0.cmp(&b.0));
-
-                        for (i, (name, is_dir)) in items.iter().enumerate() {
-                            if *is_dir {
-                                print!("\x1b[34m{:<20}\x1b[0m", name);
-                            } else {
-                                print!("{:<20}", name);
-                            }
+            Command::Help(None) => {
+                writeln!(out, "Available commands:")?;
+                for builtin in builtins::BUILTINS {
+                    writeln!(out, "  {:<16}- {}", builtin.usage, builtin.summary)?;
+                }
+                writeln!(out, "\nFeatures:")?;
+                writeln!(out, "  - Quotes: echo \"hello world\" or echo 'single quotes'")?;
+                writeln!(out, "  - Subshells: echo $(pwd) or echo `pwd`")?;
+                writeln!(out, "  - Variables: NAME=value, echo $NAME or ${{NAME}}")?;
+                writeln!(out, "  - Background: command &")?;
+                writeln!(out, "  - Pipes: command1 | command2, or |& to pipe stderr too")?;
+                writeln!(out, "  - Conditionals: cmd1 && cmd2, cmd1 || cmd2; $? is the last exit status")?;
+                writeln!(out, "  - Redirects: cmd < in > out >> append 2> err 2>&1")?;
+                writeln!(out, "  - Heredoc: cmd << EOF, here-string: cmd <<< word")?;
+                writeln!(out, "  - History: !! reruns the last command, !N entry N, !prefix the latest match")?;
+            }
 
-                            if (i + 1) % 4 == 0 {
-                                println!();
-                            }
+            Command::Disown(job_id) => {
+                if let Err(e) = job_manager.disown(*job_id) {
+                    eprintln!("disown: {}", e);
+                    return Ok(1);
+                }
+            }
+
+            Command::HelpIndex => {
+                let width = crossterm::terminal::size().map_or(80, |(cols, _)| cols as usize);
+                for line in builtins::index(width) {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+
+            Command::Help(Some(topic)) => match builtins::find(topic) {
+                Some(builtin) => {
+                    writeln!(out, "{}", builtin.usage)?;
+                    writeln!(out, "    {}", builtin.description)?;
+                }
+                None => {
+                    eprintln!("help: no help topics match '{}'", topic);
+                    return Ok(1);
+                }
+            },
+
+            Command::Ls { paths, long, all } => {
+                let paths = if paths.is_empty() { vec![".".to_string()] } else { paths.clone() };
+                let mut status = 0;
+                let mut files = Vec::new();
+                let mut dirs = Vec::new();
+                for path in &paths {
+                    match fs::metadata(path) {
+                        Ok(meta) if meta.is_dir() => dirs.push(path),
+                        Ok(_) => files.push((path.clone(), FileKind::of(Path::new(path)), PathBuf::from(path))),
+                        Err(e) => {
+                            eprintln!("ls: {}: {}", path, e);
+                            status = 1;
+                        }
+                    }
+                }
+
+                let listed_files = !files.is_empty();
+                if listed_files {
+                    write_ls_items(out, files, *long, color)?;
+                }
+                for (i, dir) in dirs.iter().enumerate() {
+                    let entries = match fs::read_dir(dir) {
+                        Ok(entries) => entries,
+                        Err(e) => {
+                            eprintln!("ls: {}: {}", dir, e);
+                            status = 1;
+                            continue;
                         }
-                        println!();
+                    };
+                    let items: Vec<_> = entries
+                        .flatten()
+                        .map(|entry| {
+                            let name = entry.file_name().to_string_lossy().to_string();
+                            let kind = FileKind::of(&entry.path());
+                            (name, kind, entry.path())
+                        })
+                        .filter(|(name, _, _)| *all || !name.starts_with('.'))
+                        .collect();
+
+                    // With several operands each directory gets a heading, as in ls.
+                    if paths.len() > 1 {
+                        let blank = if i > 0 || listed_files { "\n" } else { "" };
+                        writeln!(out, "{}{}:", blank, dir)?;
                     }
-                    Err(e) => eprintln!("ls: {}", e),
+                    write_ls_items(out, items, *long, color)?;
                 }
+                return Ok(status);
             }
 
-            Command::Cat(file) => match fs::read_to_string(file) {
-                Ok(contents) => print!("{}", contents),
-                Err(e) => eprintln!("cat: {}: {}", file, e),
-            },
+            Command::Cat(files) => {
+                let mut status = 0;
+                for file in files {
+                    match fs::read_to_string(file) {
+                        Ok(contents) => write!(out, "{}", contents)?,
+                        Err(e) => {
+                            eprintln!("cat: {}: {}", file, e);
+                            status = 1;
+                        }
+                    }
+                }
+                return Ok(status);
+            }
 
-            Command::Mkdir(dir) => {
-                if let Err(e) = fs::create_dir(dir) {
-                    eprintln!("mkdir: {}", e);
+            Command::Mkdir { paths, parents } => {
+                let mut status = 0;
+                for dir in paths {
+                    let result = if *parents { fs::create_dir_all(dir) } else { fs::create_dir(dir) };
+                    if let Err(e) = result {
+                        eprintln!("mkdir: cannot create directory '{}': {}", dir, e);
+                        status = 1;
+                    }
                 }
+                return Ok(status);
             }
 
-            Command::Rm(file) => {
-                let path = PathBuf::from(file);
-                let result = if path.is_dir() {
-                    fs::remove_dir_all(&path)
-                } else {
-                    fs::remove_file(&path)
-                };
-                if let Err(e) = result {
-                    eprintln!("rm: {}", e);
+            Command::Rm { targets, recursive, force } => {
+                let mut status = 0;
+                for target in targets {
+                    let path = Path::new(target);
+                    // A symlink to a directory is removed like a file.
+                    let is_dir = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir());
+                    let result = match (is_dir, recursive) {
+                        (true, true) => fs::remove_dir_all(path),
+                        (true, false) => {
+                            eprintln!("rm: cannot remove '{}': Is a directory", target);
+                            status = 1;
+                            continue;
+                        }
+                        (false, _) => fs::remove_file(path),
+                    };
+                    match result {
+                        Err(e) if *force && e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => {
+                            eprintln!("rm: cannot remove '{}': {}", target, e);
+                            status = 1;
+                        }
+                        Ok(()) => {}
+                    }
                 }
+                return Ok(status);
             }
 
             Command::Touch(file) => {
                 if let Err(e) = fs::File::create(file) {
                     eprintln!("touch: {}", e);
+                    return Ok(1);
                 }
             }
 
             Command::Clear => {
-                print!("\x1b[2J\x1b[H");
+                write!(out, "\x1b[2J\x1b[H")?;
+            }
+
+            Command::Sleep(duration) => {
+                if !signal_handler::interruptible_sleep(*duration) {
+                    writeln!(out)?;
+                }
+            }
+
+            Command::Assign(assignments) => {
+                for (name, value) in assignments {
+                    env::set_var(name, value);
+                }
+            }
+
+            Command::Export(vars) => {
+                if vars.is_empty() {
+                    let mut exported: Vec<(String, String)> = env::vars().collect();
+                    exported.sort();
+                    for (name, value) in exported {
+                        writeln!(out, "export {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+                    }
+                }
+                for (name, value) in vars {
+                    if let Some(value) = value {
+                        env::set_var(name, value);
+                    }
+                }
+            }
+
+            Command::Env {
+                clear,
+                assignments,
+                command,
+            } => {
+                if command.is_empty() {
+                    if !*clear {
+                        for (name, value) in env::vars() {
+                            if !assignments.iter().any(|(n, _)| *n == name) {
+                                writeln!(out, "{}={}", name, value)?;
+                            }
+                        }
+                    }
+                    for (name, value) in assignments {
+                        writeln!(out, "{}={}", name, value)?;
+                    }
+                } else {
+                    let cmd = Self::env_command(*clear, assignments, command);
+                    return Ok(Self::run_foreground(cmd, &command[0], &join_for_display(command), job_manager));
+                }
             }
 
             Command::External {
@@ -386,55 +1471,485 @@ impl Command {
             } => {
                 let mut cmd = ProcessCommand::new(program);
                 cmd.args(args);
+                let command_str = join_for_display(&[std::slice::from_ref(program), args.as_slice()].concat());
 
                 if *background {
                     cmd.stdin(Stdio::null())
                         .stdout(Stdio::inherit())
                         .stderr(Stdio::inherit());
+                    jobs::set_process_group(&mut cmd, None);
 
                     match cmd.spawn() {
                         Ok(child) => {
                             let pid = child.id();
-                            let command_str = format!("{} {}", program, args.join(" "));
-                            let job_id = job_manager.add_job(pid, command_str, child);
-                            println!("[{}] {}", job_id, pid);
-                        }
-                        Err(e) => {
-                            eprintln!("{}: {}", program, e);
+                            job_manager.add_job(pid, command_str, vec![child]);
                         }
+                        Err(e) => return Ok(Self::report_spawn_error(program, &command_str, &e)),
                     }
                 } else {
-                    match cmd.spawn() {
-                        Ok(mut child) => {
-                            let pid = child.id();
-                            job_manager.set_foreground_pid(Some(pid));
-                            
-                            let status = child.wait();
-                            
-                            job_manager.set_foreground_pid(None);
-                            
-                            match status {
-                                Ok(status) => {
-                                    if !status.success() {
-                                        if let Some(code) = status.code() {
-                                            eprintln!("{}: exited with code {}", program, code);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("{}: {}", program, e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("{}: {}", program, e);
-                        }
+                    return Ok(Self::run_foreground(cmd, program, &command_str, job_manager));
+                }
+            }
+
+            Command::Cd(_)
+            | Command::History
+            | Command::Jobs { .. }
+            | Command::Fg(_)
+            | Command::Bg(_)
+            | Command::Kill { .. }
+            | Command::Chpwd(_)
+            | Command::Source(_)
+            | Command::Reload
+            | Command::Set(_)
+            | Command::Alias(_)
+            | Command::Unalias(_)
+            | Command::Type(_)
+            | Command::Time(_)
+            | Command::Exit => {}
+        }
+        Ok(0)
+    }
+
+    /// Run `cmd` in its own process group with the terminal handed to it and
+    /// return its exit status. If it is suspended with Ctrl-Z it becomes a
+    /// stopped job named `command_line`.
+    fn run_foreground(mut cmd: ProcessCommand, program: &str, command_line: &str, job_manager: &mut JobManager) -> i32 {
+        jobs::set_process_group(&mut cmd, None);
+        match cmd.spawn() {
+            Ok(child) => {
+                let pid = child.id();
+                job_manager.set_foreground_pid(Some(pid));
+
+                let mut processes = vec![child];
+                let outcome = jobs::wait_foreground(pid, &mut processes, false, false);
+
+                job_manager.set_foreground_pid(None);
+
+                match outcome {
+                    ForegroundOutcome::Exited(0) | ForegroundOutcome::Killed(_) => {}
+                    ForegroundOutcome::Exited(code) => {
+                        eprintln!("{}: exited with code {}", program, code);
+                    }
+                    ForegroundOutcome::Stopped => {
+                        job_manager.add_stopped_job(pid, command_line.to_string(), processes);
                     }
                 }
+                outcome.status()
             }
+            Err(e) => Self::report_spawn_error(program, command_line, &e),
+        }
+    }
+
+    /// Report a command that couldn't be started. A name with no `/` that
+    /// isn't in `$PATH` is `command not found`. When it was refused for lack
+    /// of permission (exit status 126 in other shells) and `sudo` is available,
+    /// suggest running it with `sudo`. Returns the exit status: 127 for a
+    /// missing command, otherwise 126.
+    pub fn report_spawn_error(program: &str, command_line: &str, e: &io::Error) -> i32 {
+        if e.kind() == io::ErrorKind::NotFound && !program.contains('/') {
+            eprintln!("rshell: {}: command not found", program);
+        } else {
+            eprintln!("{}: {}", program, e);
+        }
+        if e.kind() == io::ErrorKind::PermissionDenied
+            && !Path::new(program).is_dir()
+            && find_in_path("sudo").is_some()
+        {
+            eprintln!("hint: try 'sudo {}'", command_line);
+        }
+        if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redirects::ParsedCommand;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_line_continuation_unquoted() {
+        assert!(Command::needs_line_continuation("echo a\\"));
+        assert!(!Command::needs_line_continuation("echo a\\\\"));
+        assert_eq!(Command::join_line_continuations("echo a\\\nb"), "echo ab");
+        match Command::parse("echo a\\\nb") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["ab"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_line_continuation_in_double_quotes() {
+        assert!(Command::needs_line_continuation("echo \"a\\"));
+        assert_eq!(Command::join_line_continuations("echo \"a\\\nb\""), "echo \"ab\"");
+        match Command::parse("echo \"a\\\nb\"") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["ab"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_line_continuation_in_single_quotes() {
+        assert!(Command::needs_line_continuation("echo 'a\\"));
+        let input = "echo 'a\\\nb'";
+        assert_eq!(Command::join_line_continuations(input), input);
+        match Command::parse(input) {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["a\\\nb"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        match Command::parse("echo $(echo hi)") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["hi"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_and_quoted_substitution() {
+        match Command::parse("echo \"$(echo $(echo a) b)\"") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["a b"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+        match Command::parse("echo '$(echo hi)'") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["$(echo hi)"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_backtick_substitution() {
+        match Command::parse("echo `echo hi` '`echo hi`'") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["hi", "`echo hi`"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+        assert_eq!(
+            Command::expand_substitutions("echo `pwd"),
+            Err("Unmatched backtick".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quoted_globs_stay_literal() {
+        match Command::parse("true '*.txt' \"?\" \\*") {
+            Ok(Some(Command::External { args, .. })) => assert_eq!(args, vec!["*.txt", "?", "*"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/projects"), format!("{}/projects", home));
+        assert_eq!(expand_tilde("a/~/b"), "a/~/b");
+        assert_eq!(expand_tilde("~no_such_user_here/x"), "~no_such_user_here/x");
+        #[cfg(unix)]
+        assert_eq!(expand_tilde("~root/x"), "/root/x");
+
+        match Command::parse("cd '~'") {
+            Ok(Some(Command::Cd(path))) => assert_eq!(path.as_deref(), Some("~")),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
 
-            Command::History | Command::Jobs | Command::Fg(_) | Command::Bg(_) => {}
+    #[test]
+    fn test_assignment_values_are_not_split() {
+        env::set_var("RSHELL_TEST_A", "one");
+        env::set_var("RSHELL_TEST_B", "two  three");
+        match Command::parse("X=\"$RSHELL_TEST_A $RSHELL_TEST_B\" Y=$RSHELL_TEST_B Z=$(echo a b)") {
+            Ok(Some(Command::Assign(assignments))) => assert_eq!(
+                assignments,
+                vec![
+                    ("X".to_string(), "one two  three".to_string()),
+                    ("Y".to_string(), "two  three".to_string()),
+                    ("Z".to_string(), "a b".to_string()),
+                ]
+            ),
+            other => panic!("unexpected parse: {:?}", other),
         }
-        true
+
+        match Command::parse("echo $RSHELL_TEST_B \"$RSHELL_TEST_B\"") {
+            Ok(Some(Command::Echo { args, .. })) => assert_eq!(args, vec!["two", "three", "two  three"]),
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_assignment_values_are_not_globbed() {
+        match Command::parse("files=*.txt") {
+            Ok(Some(Command::Assign(assignments))) => {
+                assert_eq!(assignments, vec![("files".to_string(), "*.txt".to_string())])
+            }
+            other => panic!("unexpected parse: {:?}", other),
+        }
+        match Command::parse("export files=*.txt") {
+            Ok(Some(Command::Export(vars))) => {
+                assert_eq!(vars, vec![("files".to_string(), Some("*.txt".to_string()))])
+            }
+            other => panic!("unexpected parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_env_clear_only_passes_assignments() {
+        let (clear, assignments, command) = match Command::parse("env -i FOO=bar env") {
+            Ok(Some(Command::Env { clear, assignments, command })) => (clear, assignments, command),
+            other => panic!("unexpected parse: {:?}", other),
+        };
+        assert!(clear);
+
+        let output = Command::env_command(clear, &assignments, &command).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "FOO=bar\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_in_path() {
+        assert!(find_in_path("sh").is_some_and(|path| path.ends_with("sh")));
+        assert_eq!(find_in_path("rshell-no-such-command"), None);
+    }
+
+    #[test]
+    fn test_quote_for_display() {
+        assert_eq!(quote_for_display("ls"), "ls");
+        assert_eq!(quote_for_display("dir/file~1.txt"), "dir/file~1.txt");
+        assert_eq!(quote_for_display("~/dir/file.txt"), "'~/dir/file.txt'");
+        assert_eq!(quote_for_display("~"), "'~'");
+        assert_eq!(quote_for_display(""), "''");
+        assert_eq!(quote_for_display("two words"), "'two words'");
+        assert_eq!(quote_for_display("$HOME"), "'$HOME'");
+        assert_eq!(quote_for_display("it's \"ok\""), "\"it's \\\"ok\\\"\"");
+        assert_eq!(
+            join_for_display(&["grep", "a b", "it's"]),
+            "grep 'a b' \"it's\""
+        );
+    }
+
+    #[test]
+    fn test_substitution_output_line_endings() {
+        assert_eq!(Command::substitution_output(b"out\n"), "out");
+        assert_eq!(Command::substitution_output(b"out\r\n"), "out");
+        assert_eq!(Command::substitution_output(b"a\r\nb\r\n\r\n"), "a\r\nb");
+        assert_eq!(Command::substitution_output(b"tab\r"), "tab\r");
+        assert_eq!(Command::substitution_output(b"bad \xff\n"), "bad \u{fffd}");
+    }
+
+    #[test]
+    fn test_split_unquoted() {
+        let pieces = Command::split_unquoted("a | b && echo 'x && y' || echo $(c && d)", &["&&", "||"]);
+        assert_eq!(
+            pieces,
+            vec![
+                (None, "a | b ".to_string()),
+                (Some("&&"), " echo 'x && y' ".to_string()),
+                (Some("||"), " echo $(c && d)".to_string()),
+            ]
+        );
+        let stages = Command::split_unquoted("grep \"a|b\" f | sort", &["|"]);
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[0].1, "grep \"a|b\" f ");
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let line = "ls -l \"my dir\"/x 2>/dev/null | grep 'a|b' && echo $(a | b) a2>f &";
+        let tokens: Vec<(TokenKind, &str)> =
+            Command::tokenize(line).into_iter().map(|(kind, range)| (kind, &line[range])).collect();
+        use TokenKind::*;
+        assert_eq!(
+            tokens,
+            [
+                (Word, "ls"),
+                (Word, "-l"),
+                (Quoted, "\"my dir\""),
+                (Word, "/x"),
+                (Operator, "2>"),
+                (Word, "/dev/null"),
+                (Operator, "|"),
+                (Word, "grep"),
+                (Quoted, "'a|b'"),
+                (Operator, "&&"),
+                (Word, "echo"),
+                (Word, "$(a | b)"),
+                (Word, "a2"),
+                (Operator, ">"),
+                (Word, "f"),
+                (Operator, "&"),
+            ]
+        );
+        assert_eq!(Command::tokenize("echo 'open"), [(Word, 0..4), (Quoted, 5..10)]);
+    }
+
+    #[test]
+    fn test_no_color_ls() {
+        let dir = TempDir::new("no-color");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let ls = Command::parse(&format!("ls {}", dir.display())).unwrap().unwrap();
+        let mut job_manager = JobManager::new();
+
+        let mut colored = Vec::new();
+        ls.execute_to(&mut colored, color_allowed(None, true), &mut job_manager);
+        let mut plain = Vec::new();
+        ls.execute_to(&mut plain, color_allowed(Some("1".into()), true), &mut job_manager);
+
+        assert!(String::from_utf8_lossy(&colored).contains('\x1b'));
+        assert!(!String::from_utf8_lossy(&plain).contains('\x1b'));
+        assert!(String::from_utf8_lossy(&plain).contains("sub"));
+        assert!(color_allowed(Some("".into()), true));
+        assert!(!color_allowed(None, false));
+    }
+
+    #[test]
+    fn test_command_not_found_status() {
+        let mut job_manager = JobManager::new();
+        let missing = Command::parse("rshell-no-such-command --flag").unwrap().unwrap().execute(&mut job_manager);
+        let redirected = ParsedCommand::parse("rshell-no-such-command > /dev/null").unwrap().execute().unwrap();
+        let path = Command::parse("./rshell-no-such-command").unwrap().unwrap().execute(&mut job_manager);
+
+        assert_eq!((missing, redirected, path), (127, 127, 127));
+    }
+
+    #[test]
+    fn test_rm_flags() {
+        let dir = TempDir::new("rm");
+        fs::create_dir_all(dir.join("full/inner")).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        fs::write(dir.join("full/inner/f"), "").unwrap();
+        let mut job_manager = JobManager::new();
+        let mut rm = |args: &str| Command::parse(&format!("rm {}", args)).unwrap().unwrap().execute(&mut job_manager);
+        let d = dir.display();
+
+        let file = rm(&format!("{d}/file"));
+        let file_gone = !dir.join("file").exists();
+        let no_r = rm(&format!("{d}/full"));
+        let dir_kept = dir.join("full/inner/f").exists();
+        let missing = rm(&format!("{d}/missing"));
+        let forced = rm(&format!("-rf {d}/full {d}/missing"));
+        let dir_gone = !dir.join("full").exists();
+
+        assert_eq!((file, file_gone), (0, true));
+        assert_eq!((no_r, dir_kept), (1, true));
+        assert_eq!(missing, 1);
+        assert_eq!((forced, dir_gone), (0, true));
+        assert!(Command::parse("rm").is_err());
+        assert!(Command::parse("rm -f").is_ok());
+    }
+
+    #[test]
+    fn test_mkdir_parents() {
+        let dir = TempDir::new("mkdir");
+        let mut job_manager = JobManager::new();
+        let mut mkdir = |args: &str| Command::parse(&format!("mkdir {}", args)).unwrap().unwrap().execute(&mut job_manager);
+        let d = dir.display();
+
+        let missing_parent = mkdir(&format!("{d}/a/b"));
+        let parents = mkdir(&format!("-p {d}/a/b {d}/c"));
+        let again = mkdir(&format!("{d}/a/b -p"));
+        let exists = mkdir(&format!("{d}/c"));
+        let created = dir.join("a/b").is_dir() && dir.join("c").is_dir();
+
+        assert_eq!(missing_parent, 1);
+        assert_eq!((parents, again, created), (0, 0, true));
+        assert_eq!(exists, 1);
+        assert!(Command::parse("mkdir -p").is_err());
+        assert_eq!(Command::parse("mkdir -x d").unwrap_err(), "mkdir: invalid option -- 'x'");
+        let dashed = Command::parse("mkdir -- -p");
+        assert!(matches!(dashed, Ok(Some(Command::Mkdir { paths, parents: false })) if paths == ["-p"]));
+    }
+
+    #[test]
+    fn test_ls_long_and_all() {
+        let dir = TempDir::new("ls-long");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("file"), "12345").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let mut job_manager = JobManager::new();
+        let mut list = |flags: &str| {
+            let mut out = Vec::new();
+            let ls = Command::parse(&format!("ls {} {}", flags, dir.display())).unwrap().unwrap();
+            ls.execute_to(&mut out, false, &mut job_manager);
+            String::from_utf8(out).unwrap()
+        };
+
+        let short = list("");
+        let all = list("-a");
+        let long = list("-la");
+
+        assert!(!short.contains(".hidden") && short.contains("file"));
+        assert!(all.contains(".hidden"));
+        let lines: Vec<&str> = long.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("-rw") && lines[1].contains(" 5 ") && lines[1].ends_with(" file"));
+        assert!(lines[2].starts_with("drwx") && lines[2].ends_with(" sub"));
+        assert!(Command::parse("ls -z").is_err());
+    }
+
+    #[test]
+    fn test_ls_files_and_directories() {
+        let dir = TempDir::new("ls-operands");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        fs::write(dir.join("sub/inner"), "").unwrap();
+        let d = dir.display();
+
+        let mut out = Vec::new();
+        let ls = Command::parse(&format!("ls {d}/sub {d}/file {d}/missing")).unwrap().unwrap();
+        let status = ls.execute_to(&mut out, false, &mut JobManager::new());
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().map(str::trim_end).collect();
+        assert_eq!(lines, [&format!("{d}/file"), "", &format!("{d}/sub:"), "inner"]);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_cat_operands() {
+        let dir = TempDir::new("cat-operands");
+        fs::write(dir.join("a"), "one\n").unwrap();
+        fs::write(dir.join("b"), "two\n").unwrap();
+        let d = dir.display();
+
+        let mut out = Vec::new();
+        let cat = Command::parse(&format!("cat {d}/a {d}/missing {d}/b")).unwrap().unwrap();
+        let status = cat.execute_to(&mut out, false, &mut JobManager::new());
+
+        assert_eq!(String::from_utf8(out).unwrap(), "one\ntwo\n");
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_sleep_operands() {
+        let sleep = Command::parse("sleep 1 2m 0.5s");
+        assert!(matches!(sleep, Ok(Some(Command::Sleep(d))) if d == Duration::from_secs_f64(121.5)));
+        assert!(Command::parse("sleep 1e19 1e19").is_err());
+        assert!(Command::parse("sleep -1").is_err());
+        assert!(Command::parse("sleep").is_err());
+    }
+
+    #[test]
+    fn test_echo_flags() {
+        let echo = |line: &str| {
+            let mut out = Vec::new();
+            let cmd = Command::parse(line).unwrap().unwrap();
+            cmd.execute_to(&mut out, false, &mut JobManager::new());
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(echo("echo -n a b"), "a b");
+        assert_eq!(echo("echo -e 'a\\tb\\x41\\0101'"), "a\tbAA\n");
+        assert_eq!(echo("echo -ne 'a\\nb'"), "a\nb");
+        assert_eq!(echo("echo -e 'a\\cb'"), "a");
+        assert_eq!(echo("echo -e -E 'a\\tb'"), "a\\tb\n");
+        assert_eq!(echo("echo 'a\\tb' -n"), "a\\tb -n\n");
+        assert_eq!(echo("echo -nx"), "-nx\n");
+        assert_eq!(echo("echo \"a\\tb\""), echo("echo -e 'a\\tb'"));
+
+        let mut out = Vec::new();
+        let raw = Command::parse("echo -e '\\xff\\0303é'").unwrap().unwrap();
+        raw.execute_to(&mut out, false, &mut JobManager::new());
+        assert_eq!(out, b"\xff\xc3\xc3\xa9\n");
     }
 }
@@ -0,0 +1,167 @@
+use super::completion::{list_dir_matches, split_dir_prefix, MatchOptions};
+use crate::command::expand_tilde;
+use std::fs;
+
+/// Supplies Tab completions for the arguments of a particular command.
+pub trait Completer {
+    /// Return the candidates that can replace `token`, the word under the cursor.
+    /// `line` is the buffer up to the cursor.
+    fn complete(&self, line: &str, token: &str) -> Vec<String>;
+}
+
+/// Offers only directories, for `cd`.
+pub struct DirectoryCompleter;
+
+impl Completer for DirectoryCompleter {
+    fn complete(&self, _line: &str, token: &str) -> Vec<String> {
+        let options = MatchOptions {
+            dirs_only: true,
+            ..MatchOptions::from_env()
+        };
+        complete_path(token, options)
+    }
+}
+
+/// Offers host names from `~/.ssh/config` and `~/.ssh/known_hosts`, for `ssh`
+/// and `scp`. Any `user@` in the word is kept.
+pub struct HostCompleter {
+    /// Complete like `scp`: hosts get a trailing `:`, and words that aren't
+    /// host names complete as filenames, including the path after `host:`.
+    pub scp: bool,
+}
+
+impl Completer for HostCompleter {
+    fn complete(&self, _line: &str, token: &str) -> Vec<String> {
+        let options = MatchOptions::from_env();
+
+        if self.scp {
+            if let Some((host, path)) = token.split_once(':') {
+                return complete_path(path, options)
+                    .into_iter()
+                    .map(|path| format!("{}:{}", host, path))
+                    .collect();
+            }
+        }
+
+        let (user, word) = match token.split_once('@') {
+            Some((user, word)) => (format!("{}@", user), word),
+            None => (String::new(), token),
+        };
+        let suffix = if self.scp { ":" } else { "" };
+        let mut matches: Vec<String> = known_hosts()
+            .into_iter()
+            .filter(|host| options.matches(host, word))
+            .map(|host| format!("{}{}{}", user, host, suffix))
+            .collect();
+
+        if self.scp && user.is_empty() {
+            matches.extend(complete_path(token, options));
+        }
+        matches
+    }
+}
+
+/// Complete `token` as a path, relative to the current directory unless it
+/// contains a `/`.
+fn complete_path(token: &str, options: MatchOptions) -> Vec<String> {
+    let (dir, prefix) = split_dir_prefix(token).unwrap_or_else(|| (".".to_string(), token.to_string()));
+    let matches = list_dir_matches(&expand_tilde(&dir), &prefix, options).unwrap_or_default();
+
+    matches
+        .into_iter()
+        .map(|name| {
+            if token.contains('/') {
+                format!("{}/{}", dir.trim_end_matches('/'), name)
+            } else {
+                name
+            }
+        })
+        .collect()
+}
+
+/// Sorted host names from the user's ssh config and known hosts.
+fn known_hosts() -> Vec<String> {
+    let read = |path: &str| fs::read_to_string(expand_tilde(path)).unwrap_or_default();
+    let mut hosts = parse_ssh_config_hosts(&read("~/.ssh/config"));
+    hosts.extend(parse_known_hosts(&read("~/.ssh/known_hosts")));
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// Names on `Host` lines of an ssh config, skipping wildcard patterns.
+fn parse_ssh_config_hosts(config: &str) -> Vec<String> {
+    config
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (keyword, rest) = line.split_once(|c: char| c.is_whitespace() || c == '=')?;
+            keyword.eq_ignore_ascii_case("host").then_some(rest)
+        })
+        .flat_map(|rest| rest.split(|c: char| c.is_whitespace() || c == '='))
+        .filter(|name| !name.is_empty() && !name.contains(['*', '?', '!']))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Host names from the first field of each known_hosts line. Hashed entries
+/// can't be read back and are skipped; `[host]:port` gives `host`.
+fn parse_known_hosts(known_hosts: &str) -> Vec<String> {
+    known_hosts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let first = fields.next()?;
+            // `@cert-authority` and `@revoked` markers come before the hosts.
+            if first.starts_with('@') {
+                fields.next()
+            } else {
+                Some(first)
+            }
+        })
+        .filter(|field| !field.starts_with('#') && !field.starts_with('|'))
+        .flat_map(|field| field.split(','))
+        .map(|host| {
+            host.strip_prefix('[')
+                .and_then(|h| h.split_once("]:"))
+                .map_or(host, |(h, _)| h)
+                .to_string()
+        })
+        .filter(|host| !host.is_empty() && !host.contains(['*', '?', '!']))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_directory_completer_skips_files() {
+        let dir = TempDir::new("completer");
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::File::create(dir.join("doc.txt")).unwrap();
+        let token = format!("{}/do", dir.display());
+
+        let matches = DirectoryCompleter.complete(&format!("cd {}", token), &token);
+
+        assert_eq!(matches, vec![format!("{}/docs/", dir.display())]);
+    }
+
+    #[test]
+    fn test_parse_ssh_config_hosts() {
+        let config = "Host web db.internal\n  HostName 10.0.0.1\nhost=backup\nHost *.example.com\n";
+        assert_eq!(parse_ssh_config_hosts(config), vec!["web", "db.internal", "backup"]);
+    }
+
+    #[test]
+    fn test_parse_known_hosts() {
+        let known_hosts = "github.com,140.82.112.3 ssh-ed25519 AAAA\n\
+                           [git.local]:2222 ssh-rsa AAAA\n\
+                           |1|abc=|def= ssh-rsa AAAA\n\
+                           @cert-authority *.corp ssh-rsa AAAA\n\
+                           # comment\n";
+        assert_eq!(parse_known_hosts(known_hosts), vec!["github.com", "140.82.112.3", "git.local"]);
+    }
+}
@@ -1,10 +1,46 @@
 use std::fs;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::io;
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+/// How a typed word is matched against completion candidates. Everything is
+/// off by default; `RSHELL_COMPLETE_IGNORE_CASE` and `RSHELL_COMPLETE_SUBSTRING`
+/// turn on the matching options.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchOptions {
+    pub ignore_case: bool,
+    /// Match the word anywhere in the name rather than only at the start.
+    pub substring: bool,
+    /// Only offer directories, e.g. for `cd`.
+    pub dirs_only: bool,
+}
+
+impl MatchOptions {
+    pub fn from_env() -> Self {
+        Self {
+            ignore_case: env::var_os("RSHELL_COMPLETE_IGNORE_CASE").is_some(),
+            substring: env::var_os("RSHELL_COMPLETE_SUBSTRING").is_some(),
+            dirs_only: false,
+        }
+    }
+
+    pub fn matches(&self, name: &str, word: &str) -> bool {
+        let (name, word) = if self.ignore_case {
+            (name.to_lowercase(), word.to_lowercase())
+        } else {
+            (name.to_string(), word.to_string())
+        };
+        if self.substring {
+            name.contains(&word)
+        } else {
+            name.starts_with(&word)
+        }
+    }
+}
+
 pub fn split_dir_prefix(path: &str) -> Option<(String, String)> {
     if let Some(idx) = path.rfind('/') {
         let dir = if idx == 0 {
@@ -19,15 +55,23 @@ pub fn split_dir_prefix(path: &str) -> Option<(String, String)> {
     }
 }
 
-pub fn list_dir_matches(dir: &str, prefix: &str) -> io::Result<Vec<String>> {
+/// List entries of `dir` starting with `prefix`. A directory that is missing or
+/// can't be read has nothing to complete, so it yields no matches.
+pub fn list_dir_matches(dir: &str, prefix: &str, options: MatchOptions) -> io::Result<Vec<String>> {
     let mut matches = Vec::new();
-    let entries = fs::read_dir(dir)?;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound) => {
+            return Ok(matches);
+        }
+        Err(e) => return Err(e),
+    };
     for entry in entries.flatten() {
         let name = entry.file_name().to_string_lossy().to_string();
-        if name.starts_with(prefix) {
+        if options.matches(&name, prefix) {
             if entry.path().is_dir() {
                 matches.push(format!("{}/", name));
-            } else {
+            } else if !options.dirs_only {
                 matches.push(name);
             }
         }
@@ -36,37 +80,80 @@ pub fn list_dir_matches(dir: &str, prefix: &str) -> io::Result<Vec<String>> {
     Ok(matches)
 }
 
-pub fn list_path_commands(prefix: &str) -> io::Result<Vec<String>> {
-    let mut matches = Vec::new();
-    if let Ok(path_var) = env::var("PATH") {
-        for dir in path_var.split(':') {
-            if let Ok(entries) = fs::read_dir(dir) {
-                for entry in entries.flatten() {
-                    let name = entry.file_name().to_string_lossy().to_string();
-                    if name.starts_with(prefix) {
-                        #[cfg(unix)]
-                        {
-                            if let Ok(meta) = entry.metadata() {
-                                if meta.permissions().mode() & 0o111 != 0 {
-                                    matches.push(name);
-                                }
-                            }
-                        }
-                        #[cfg(not(unix))]
-                        {
-                            matches.push(name);
+/// Executables on `$PATH`, scanned once and reused until `$PATH` changes,
+/// since reading every PATH directory on each Tab is slow.
+#[derive(Default)]
+pub struct PathCache {
+    /// The `$PATH` the commands were read from; `None` before the first scan.
+    path: Option<OsString>,
+    commands: Vec<String>,
+}
+
+impl PathCache {
+    /// Rescan if `$PATH` changed since the last scan.
+    pub fn refresh(&mut self) {
+        let path = env::var_os("PATH").unwrap_or_default();
+        if self.path.as_ref() != Some(&path) {
+            self.commands = scan_path_commands(&path);
+            self.path = Some(path);
+        }
+    }
+
+    /// Every cached command, sorted.
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// Cached commands matching `prefix`, sorted.
+    pub fn matching(&self, prefix: &str, options: MatchOptions) -> Vec<String> {
+        self.commands
+            .iter()
+            .filter(|name| options.matches(name, prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Sorted names of the executables in the directories of `path`.
+fn scan_path_commands(path: &OsStr) -> Vec<String> {
+    let mut commands = Vec::new();
+    for dir in env::split_paths(path) {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                #[cfg(unix)]
+                {
+                    if let Ok(meta) = entry.metadata() {
+                        if meta.permissions().mode() & 0o111 != 0 {
+                            commands.push(name);
                         }
                     }
                 }
+                #[cfg(not(unix))]
+                {
+                    commands.push(name);
+                }
             }
         }
     }
+    commands.sort();
+    commands.dedup();
+    commands
+}
+
+/// Names of environment variables starting with `prefix`, for `$` completion.
+pub fn list_env_vars(prefix: &str) -> Vec<String> {
+    let mut matches: Vec<String> = env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
     matches.sort();
-    matches.dedup();
-    Ok(matches)
+    matches
 }
 
-pub fn common_prefix(strings: &[String]) -> String {
+/// Longest prefix shared by all `strings`. With `ignore_case`, chars that differ
+/// only in case still count as shared and are taken from the first string.
+pub fn common_prefix(strings: &[String], ignore_case: bool) -> String {
     if strings.is_empty() {
         return String::new();
     }
@@ -77,9 +164,102 @@ pub fn common_prefix(strings: &[String]) -> String {
             first
                 .chars()
                 .zip(s.chars())
-                .take_while(|(a, b)| a == b)
+                .take_while(|(a, b)| {
+                    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+                })
                 .count(),
         );
     }
     first.chars().take(prefix_len).collect()
 }
+
+/// Columns and rows for a grid of `count` items in cells `cell_width` wide,
+/// fitting in `width` terminal columns. Always at least one column.
+pub fn grid_layout(count: usize, cell_width: usize, width: usize) -> (usize, usize) {
+    let cols = (width / cell_width.max(1)).clamp(1, count.max(1));
+    (cols, count.div_ceil(cols))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_dir_matches_unreadable_directory() {
+        let dir = TempDir::new("noperm");
+        fs::File::create(dir.join("secret.txt")).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let result = list_dir_matches(dir.to_str().unwrap(), "se", MatchOptions::default());
+        // Root can read the directory regardless of its mode.
+        let readable = fs::read_dir(&dir).is_ok();
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // An unreadable directory offers nothing; as root it is listed as usual.
+        let expected: Vec<String> = if readable { vec!["secret.txt".to_string()] } else { Vec::new() };
+        assert_eq!(result.unwrap(), expected);
+        assert!(list_dir_matches("/nonexistent-rshell-dir", "", MatchOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_list_dir_matches_ignore_case_and_substring() {
+        let dir = TempDir::new("complete");
+        fs::create_dir_all(dir.join("Documents")).unwrap();
+        fs::File::create(dir.join("notes.doc")).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let exact = MatchOptions::default();
+        let ignore_case = MatchOptions { ignore_case: true, ..Default::default() };
+        let anywhere = MatchOptions { ignore_case: true, substring: true, ..Default::default() };
+        let dirs_only = MatchOptions { substring: true, dirs_only: true, ..Default::default() };
+        let exact_matches = list_dir_matches(dir_str, "doc", exact).unwrap();
+        let ignore_case_matches = list_dir_matches(dir_str, "doc", ignore_case).unwrap();
+        let anywhere_matches = list_dir_matches(dir_str, "doc", anywhere).unwrap();
+        let dirs_only_matches = list_dir_matches(dir_str, "o", dirs_only).unwrap();
+
+        assert!(exact_matches.is_empty());
+        assert_eq!(ignore_case_matches, vec!["Documents/"]);
+        assert_eq!(anywhere_matches, vec!["Documents/", "notes.doc"]);
+        assert_eq!(dirs_only_matches, vec!["Documents/"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_path_commands_only_executables() {
+        let dir = TempDir::new("path");
+        fs::File::create(dir.join("tool")).unwrap();
+        fs::set_permissions(dir.join("tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::File::create(dir.join("notes")).unwrap();
+
+        let commands = scan_path_commands(dir.as_os_str());
+
+        assert_eq!(commands, vec!["tool"]);
+    }
+
+    #[test]
+    fn test_list_env_vars() {
+        env::set_var("RSHELL_TEST_COMPLETE_VAR", "1");
+        assert_eq!(list_env_vars("RSHELL_TEST_COMPLETE_V"), vec!["RSHELL_TEST_COMPLETE_VAR"]);
+        assert!(list_env_vars("RSHELL_TEST_NO_SUCH_").is_empty());
+    }
+
+    #[test]
+    fn test_common_prefix_ignore_case() {
+        let names = vec!["Documents".to_string(), "docs".to_string()];
+        assert_eq!(common_prefix(&names, false), "");
+        assert_eq!(common_prefix(&names, true), "Doc");
+    }
+
+    #[test]
+    fn test_grid_layout() {
+        assert_eq!(grid_layout(10, 12, 80), (6, 2));
+        assert_eq!(grid_layout(3, 12, 80), (3, 1));
+        assert_eq!(grid_layout(4, 100, 80), (1, 4));
+        assert_eq!(grid_layout(0, 12, 80), (1, 0));
+    }
+}
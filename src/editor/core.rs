@@ -1,94 +1,305 @@
+use crate::builtins;
+use crate::command::{expand_tilde, is_executable};
 use crate::history::History;
+use colored::Colorize;
 use crossterm::{
     cursor,
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    execute,
+    execute, queue,
     style::Print,
     terminal::{self, ClearType},
 };
+use std::collections::HashMap;
+use std::env;
 use std::io::{self, Write};
+use std::path::Path;
+use unicode_width::UnicodeWidthChar;
+use super::completer::{Completer, DirectoryCompleter, HostCompleter};
 use super::completion::*;
+use super::highlight::highlight;
 use super::raw_mode::RawModeGuard;
 
+const KILL_RING_MAX: usize = 32;
+
+/// Spaces between columns when listing completions.
+const COLUMN_GAP: usize = 2;
+
+/// Listing more completions than this asks for confirmation first, like bash.
+const COMPLETION_QUERY_ITEMS: usize = 100;
+
+/// Number of terminal columns `s` occupies, skipping ANSI color sequences.
+fn display_width(s: &str) -> usize {
+    let mut in_escape = false;
+    let mut width = 0;
+
+    for c in s.chars() {
+        if c == '\x1b' {
+            in_escape = true;
+            continue;
+        }
+        if in_escape {
+            if c == 'm' {
+                in_escape = false;
+            }
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
+/// Key bindings used by the editor, chosen with `set -o emacs` / `set -o vi`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditMode {
+    Emacs,
+    /// Emacs-style keys while inserting; Escape switches to vi command keys.
+    Vi,
+}
+
+/// Which side of the cursor a kill removed text from. Kills in the same
+/// direction straight after each other add to one kill ring entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KillDirection {
+    Forward,
+    Backward,
+}
+
+/// How Alt+U, Alt+L and Alt+C change the case of a word.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WordCase {
+    Upper,
+    Lower,
+    /// Uppercase the first letter and lowercase the rest.
+    Capitalize,
+}
+
+/// How an incremental history search ended.
+enum SearchOutcome {
+    /// Keep the match in the buffer and continue editing.
+    Accept,
+    /// Run the match immediately (Enter).
+    Execute,
+    /// The original buffer was restored.
+    Cancel,
+}
+
 pub struct LineEditor {
     buffer: String,
     cursor_pos: usize,
     history_index: Option<usize>,
+    kill_ring: Vec<String>,
+    yank_index: usize,
+    /// Direction of the kill made by the last key, if it made one.
+    last_kill: Option<KillDirection>,
+    /// Char range `(start, len)` of the text inserted by the last yank, so Alt+Y can replace it.
+    last_yank: Option<(usize, usize)>,
+    /// Text that PageUp/PageDown history search is matching against, while a search is active.
+    history_prefix: Option<String>,
+    /// While Alt+P cycles through history completions: the text typed before
+    /// the first press and the index of the completion shown.
+    history_completion: Option<(String, usize)>,
+    /// Terminal row the cursor is on, counted from the row the prompt starts on.
+    cursor_row: usize,
+    /// `(buffer, cursor_pos)` before each edit, newest last, for Ctrl+_.
+    undo_stack: Vec<(String, usize)>,
+    /// Whether the last key typed a word character, so the next one joins its undo step.
+    coalesce_insert: bool,
+    /// Argument completers, keyed by command name.
+    completers: HashMap<String, Box<dyn Completer>>,
+    /// Executables on `$PATH`, for command completion and highlighting.
+    path_cache: PathCache,
+    /// Names of the shell's aliases, highlighted as commands.
+    aliases: Vec<String>,
+    mode: EditMode,
+    /// In vi mode, whether keys are commands rather than text. Each line starts inserting.
+    vi_command_mode: bool,
 }
 
 impl LineEditor {
     pub fn new() -> Self {
-        Self {
+        let mut editor = Self {
             buffer: String::new(),
             cursor_pos: 0,
             history_index: None,
-        }
+            kill_ring: Vec::new(),
+            yank_index: 0,
+            last_kill: None,
+            last_yank: None,
+            history_prefix: None,
+            history_completion: None,
+            cursor_row: 0,
+            undo_stack: Vec::new(),
+            coalesce_insert: false,
+            completers: HashMap::new(),
+            path_cache: PathCache::default(),
+            aliases: Vec::new(),
+            mode: EditMode::Emacs,
+            vi_command_mode: false,
+        };
+        editor.register_completer("cd", Box::new(DirectoryCompleter));
+        editor.register_completer("ssh", Box::new(HostCompleter { scp: false }));
+        editor.register_completer("scp", Box::new(HostCompleter { scp: true }));
+        editor
+    }
+
+    /// Complete the arguments of `command` with `completer` instead of filenames.
+    pub fn register_completer(&mut self, command: &str, completer: Box<dyn Completer>) {
+        self.completers.insert(command.to_string(), completer);
+    }
+
+    /// Switch key bindings. Takes effect from the next line read.
+    pub fn set_mode(&mut self, mode: EditMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> EditMode {
+        self.mode
+    }
+
+    /// Make sure the cached `$PATH` commands are current. Called before each
+    /// command completion, so the scan happens on first use and again only
+    /// when `$PATH` changes.
+    pub fn refresh_path_cache(&mut self) {
+        self.path_cache.refresh();
+    }
+
+    /// Tell the editor which aliases are defined, so they highlight as commands.
+    pub fn set_aliases(&mut self, aliases: Vec<String>) {
+        self.aliases = aliases;
+    }
+
+    /// The executables on `$PATH`, rescanned first if `$PATH` changed.
+    pub fn path_commands(&mut self) -> &[String] {
+        self.path_cache.refresh();
+        self.path_cache.commands()
     }
 
-    pub fn read_line(&mut self, prompt: &str, history: &mut History) -> io::Result<String> {
+    /// Read a line at `prompt`. Returns `None` for end of input, Ctrl-D on an
+    /// empty line.
+    pub fn read_line(&mut self, prompt: &str, history: &mut History) -> io::Result<Option<String>> {
         loop {
             self.buffer.clear();
             self.cursor_pos = 0;
             self.history_index = None;
+            self.cursor_row = 0;
+            self.undo_stack.clear();
+            self.coalesce_insert = false;
+            self.vi_command_mode = false;
 
             let mut stdout = io::stdout();
             let _guard = RawModeGuard::enter()?;
 
-            execute!(stdout, Print(prompt))?;
+            execute!(stdout, Print(prompt.replace('\n', "\r\n")))?;
             stdout.flush()?;
-
-            let mut ctrl_c_pressed = false;
+            // A prompt with several lines leaves the cursor below its first row.
+            self.cursor_row = Self::layout(prompt, "", 0, Self::terminal_width()).0;
 
             loop {
-                if let Event::Key(key_event) = event::read()? {
+                let event = event::read()?;
+                if let Event::Paste(text) = &event {
+                    self.paste(text, prompt)?;
+                }
+                if let Event::Key(key_event) = event {
+                    let previous_yank = self.last_yank.take();
+                    let previous_kill = self.last_kill.take();
+                    let history_prefix = self.history_prefix.take();
+                    let history_completion = self.history_completion.take();
+                    let snapshot = (self.buffer.clone(), self.cursor_pos);
+                    let is_insert = matches!(
+                        key_event,
+                        KeyEvent {
+                            code: KeyCode::Char(c),
+                            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                            ..
+                        } if !c.is_whitespace() && !self.vi_command_mode
+                    );
+                    let mut is_undo = false;
                     match key_event {
+                        KeyEvent {
+                            code: KeyCode::Esc,
+                            ..
+                        } if self.mode == EditMode::Vi && !self.vi_command_mode => {
+                            // Like vi, leaving insert mode steps back onto the last char typed.
+                            self.vi_command_mode = true;
+                            self.cursor_pos = self.cursor_pos.saturating_sub(1);
+                            self.update_cursor_position(prompt)?;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Char(c),
+                            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                            ..
+                        } if self.vi_command_mode => {
+                            is_undo = c == 'u';
+                            self.vi_command(c, prompt, history)?;
+                        }
+
                         KeyEvent {
                             code: KeyCode::Enter,
                             ..
                         } => {
-                            execute!(stdout, Print("\r\n"))?;
-                            return Ok(self.buffer.clone());
+                            self.finish_line(prompt)?;
+                            return Ok(Some(self.buffer.clone()));
                         }
 
                         KeyEvent {
                             code: KeyCode::Backspace,
+                            modifiers: KeyModifiers::ALT,
                             ..
                         } => {
-                            if self.cursor_pos > 0 {
-                                self.cursor_pos -= 1;
-                                self.buffer.remove(self.cursor_pos);
-                                self.redraw(prompt)?;
-                            }
+                            self.kill_word_backward(previous_kill);
+                            self.redraw(prompt)?;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Backspace,
+                            ..
+                        } if self.cursor_pos > 0 => {
+                            self.cursor_pos -= 1;
+                            self.buffer.remove(self.byte_index_at_char_pos(self.cursor_pos));
+                            self.redraw(prompt)?;
                         }
 
                         KeyEvent {
                             code: KeyCode::Delete,
                             ..
+                        } if self.cursor_pos < self.buffer.chars().count() => {
+                            self.buffer.remove(self.byte_index_at_char_pos(self.cursor_pos));
+                            self.redraw(prompt)?;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Char('b') | KeyCode::Left,
+                            modifiers: KeyModifiers::ALT,
+                            ..
                         } => {
-                            if self.cursor_pos < self.buffer.len() {
-                                self.buffer.remove(self.cursor_pos);
-                                self.redraw(prompt)?;
-                            }
+                            self.cursor_pos = self.previous_word_start();
+                            self.update_cursor_position(prompt)?;
                         }
 
                         KeyEvent {
-                            code: KeyCode::Left,
+                            code: KeyCode::Char('f') | KeyCode::Right,
+                            modifiers: KeyModifiers::ALT,
                             ..
                         } => {
-                            if self.cursor_pos > 0 {
-                                self.cursor_pos -= 1;
-                                self.update_cursor_position(prompt)?;
-                            }
+                            self.cursor_pos = self.next_word_end();
+                            self.update_cursor_position(prompt)?;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Left,
+                            ..
+                        } if self.cursor_pos > 0 => {
+                            self.cursor_pos -= 1;
+                            self.update_cursor_position(prompt)?;
                         }
 
                         KeyEvent {
                             code: KeyCode::Right,
                             ..
-                        } => {
-                            if self.cursor_pos < self.buffer.len() {
-                                self.cursor_pos += 1;
-                                self.update_cursor_position(prompt)?;
-                            }
+                        } if self.cursor_pos < self.buffer.chars().count() => {
+                            self.cursor_pos += 1;
+                            self.update_cursor_position(prompt)?;
                         }
 
                         KeyEvent {
@@ -118,6 +329,56 @@ impl LineEditor {
                             }
                         }
 
+                        KeyEvent {
+                            code: KeyCode::PageUp,
+                            ..
+                        } => {
+                            // Like Up, but only visit entries starting with the text typed before the search began.
+                            let prefix = history_prefix
+                                .unwrap_or_else(|| self.buffer[..self.byte_index_at_char_pos(self.cursor_pos)].to_string());
+                            if let Some(entry) = history.previous_with_prefix(&prefix) {
+                                self.buffer = entry.clone();
+                                self.cursor_pos = self.buffer.chars().count();
+                                self.redraw(prompt)?;
+                            }
+                            self.history_prefix = Some(prefix);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::PageDown,
+                            ..
+                        } => {
+                            let prefix = history_prefix
+                                .unwrap_or_else(|| self.buffer[..self.byte_index_at_char_pos(self.cursor_pos)].to_string());
+                            self.buffer = history.next_with_prefix(&prefix).cloned().unwrap_or_else(|| prefix.clone());
+                            self.cursor_pos = self.buffer.chars().count();
+                            self.redraw(prompt)?;
+                            self.history_prefix = Some(prefix);
+                        }
+
+                        // Complete the line in place from history. Pressing it again shows the
+                        // next one, newest first, wrapping around at the end.
+                        KeyEvent {
+                            code: KeyCode::Char('p'),
+                            modifiers: KeyModifiers::ALT,
+                            ..
+                        } => {
+                            let (prefix, index) = match history_completion {
+                                Some((prefix, index)) => (prefix, index + 1),
+                                None => (self.buffer[..self.byte_index_at_char_pos(self.cursor_pos)].to_string(), 0),
+                            };
+                            let completions = history.history_completions(&prefix);
+                            if completions.is_empty() {
+                                execute!(stdout, Print("\x07"))?;
+                            } else {
+                                let index = index % completions.len();
+                                self.buffer = completions[index].clone();
+                                self.cursor_pos = self.buffer.chars().count();
+                                self.redraw(prompt)?;
+                                self.history_completion = Some((prefix, index));
+                            }
+                        }
+
                         KeyEvent {
                             code: KeyCode::Home,
                             ..
@@ -137,10 +398,8 @@ impl LineEditor {
                         KeyEvent {
                             code: KeyCode::Tab,
                             ..
-                        } => {
-                            if self.handle_tab_completion(prompt)? {
-                                self.redraw(prompt)?;
-                            }
+                        } if self.handle_tab_completion(prompt)? => {
+                            self.redraw(prompt)?;
                         }
 
                         KeyEvent {
@@ -166,7 +425,8 @@ impl LineEditor {
                             modifiers: KeyModifiers::CONTROL,
                             ..
                         } => {
-                            self.buffer.truncate(self.byte_index_at_char_pos(self.cursor_pos));
+                            let killed = self.buffer.split_off(self.byte_index_at_char_pos(self.cursor_pos));
+                            self.kill(killed, KillDirection::Forward, previous_kill);
                             self.redraw(prompt)?;
                         }
 
@@ -176,7 +436,8 @@ impl LineEditor {
                             ..
                         } => {
                             let bytes_to_remove = self.byte_index_at_char_pos(self.cursor_pos);
-                            self.buffer.drain(0..bytes_to_remove);
+                            let killed: String = self.buffer.drain(0..bytes_to_remove).collect();
+                            self.kill(killed, KillDirection::Backward, previous_kill);
                             self.cursor_pos = 0;
                             self.redraw(prompt)?;
                         }
@@ -186,32 +447,65 @@ impl LineEditor {
                             modifiers: KeyModifiers::CONTROL,
                             ..
                         } => {
-                            if self.cursor_pos > 0 {
-                                let mut word_end = self.cursor_pos;
-
-                                while word_end > 0 {
-                                    let prev_char = self.buffer.chars().nth(word_end - 1).unwrap();
-                                    if !prev_char.is_whitespace() {
-                                        break;
-                                    }
-                                    word_end -= 1;
-                                }
+                            self.kill_word_backward(previous_kill);
+                            self.redraw(prompt)?;
+                        }
 
-                                let mut word_start = word_end;
-                                while word_start > 0 {
-                                    let prev_char = self.buffer.chars().nth(word_start - 1).unwrap();
-                                    if prev_char.is_whitespace() {
-                                        break;
-                                    }
-                                    word_start -= 1;
-                                }
+                        KeyEvent {
+                            code: KeyCode::Char('d'),
+                            modifiers: KeyModifiers::ALT,
+                            ..
+                        } => {
+                            self.kill_word_forward(previous_kill);
+                            self.redraw(prompt)?;
+                        }
 
-                                let byte_start = self.byte_index_at_char_pos(word_start);
-                                let byte_end = self.byte_index_at_char_pos(word_end);
-                                self.buffer.drain(byte_start..byte_end);
-                                self.cursor_pos = word_start;
+                        KeyEvent {
+                            code: KeyCode::Char(c @ ('u' | 'l' | 'c')),
+                            modifiers: KeyModifiers::ALT,
+                            ..
+                        } => {
+                            let case = match c {
+                                'u' => WordCase::Upper,
+                                'l' => WordCase::Lower,
+                                _ => WordCase::Capitalize,
+                            };
+                            self.change_word_case(case);
+                            self.redraw(prompt)?;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Char('r'),
+                            modifiers: KeyModifiers::CONTROL,
+                            ..
+                        } => match self.reverse_search(history)? {
+                            SearchOutcome::Execute => {
+                                self.redraw(prompt)?;
+                                self.finish_line(prompt)?;
+                                return Ok(Some(self.buffer.clone()));
+                            }
+                            SearchOutcome::Accept | SearchOutcome::Cancel => self.redraw(prompt)?,
+                        },
+
+                        // Ctrl-X Ctrl-R runs `reload`; any other key after Ctrl-X just beeps.
+                        KeyEvent {
+                            code: KeyCode::Char('x'),
+                            modifiers: KeyModifiers::CONTROL,
+                            ..
+                        } => {
+                            let next = loop {
+                                if let Event::Key(next) = event::read()? {
+                                    break next;
+                                }
+                            };
+                            if next.code == KeyCode::Char('r') && next.modifiers == KeyModifiers::CONTROL {
+                                self.buffer = "reload".to_string();
+                                self.cursor_pos = self.buffer.chars().count();
                                 self.redraw(prompt)?;
+                                self.finish_line(prompt)?;
+                                return Ok(Some(self.buffer.clone()));
                             }
+                            execute!(stdout, Print("\x07"))?;
                         }
 
                         KeyEvent {
@@ -219,7 +513,8 @@ impl LineEditor {
                             modifiers: KeyModifiers::CONTROL,
                             ..
                         } => {
-                            execute!(io::stdout(), terminal::Clear(ClearType::All))?;
+                            execute!(io::stdout(), terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+                            self.cursor_row = 0;
                             self.redraw(prompt)?;
                         }
 
@@ -228,10 +523,9 @@ impl LineEditor {
                             modifiers: KeyModifiers::CONTROL,
                             ..
                         } => {
+                            self.finish_line(prompt)?;
                             self.buffer.clear();
                             self.cursor_pos = 0;
-                            execute!(stdout, Print("\r\n"))?;
-                            ctrl_c_pressed = true;
                             break;
                         }
 
@@ -239,36 +533,74 @@ impl LineEditor {
                             code: KeyCode::Char('d'),
                             modifiers: KeyModifiers::CONTROL,
                             ..
-                        } => {
-                            if self.buffer.is_empty() {
-                                drop(_guard);
-                                println!();
-                                std::process::exit(0);
-                            }
+                        } if self.buffer.is_empty() => {
+                            drop(_guard);
+                            println!();
+                            return Ok(None);
                         }
 
                         KeyEvent {
                             code: KeyCode::Char('y'),
                             modifiers: KeyModifiers::CONTROL,
                             ..
-                        } => {
-                            execute!(stdout, Print("\x07"))?;
-                        }
+                        } => match self.kill_ring.last().cloned() {
+                            Some(text) => {
+                                self.yank_index = self.kill_ring.len() - 1;
+                                self.yank(&text);
+                                self.redraw(prompt)?;
+                            }
+                            None => execute!(stdout, Print("\x07"))?,
+                        },
+
+                        KeyEvent {
+                            code: KeyCode::Char('y'),
+                            modifiers: KeyModifiers::ALT,
+                            ..
+                        } => match previous_yank {
+                            // Alt+Y only works straight after a yank: swap in the next older kill.
+                            Some((start, len)) => {
+                                let byte_start = self.byte_index_at_char_pos(start);
+                                let byte_end = self.byte_index_at_char_pos(start + len);
+                                self.buffer.drain(byte_start..byte_end);
+                                self.cursor_pos = start;
+
+                                self.yank_index = (self.yank_index + self.kill_ring.len() - 1) % self.kill_ring.len();
+                                let text = self.kill_ring[self.yank_index].clone();
+                                self.yank(&text);
+                                self.redraw(prompt)?;
+                            }
+                            None => execute!(stdout, Print("\x07"))?,
+                        },
 
                         KeyEvent {
                             code: KeyCode::Char('t'),
                             modifiers: KeyModifiers::CONTROL,
                             ..
-                        } => {
-                            if self.cursor_pos > 0 && self.cursor_pos < self.buffer.chars().count() {
-                                let left_idx = self.byte_index_at_char_pos(self.cursor_pos - 1);
-                                let right_idx = self.byte_index_at_char_pos(self.cursor_pos);
-                                let left_char = self.buffer.chars().nth(self.cursor_pos - 1).unwrap();
-                                let right_char = self.buffer.chars().nth(self.cursor_pos).unwrap();
-
-                                self.buffer.replace_range(left_idx..right_idx, &format!("{}{}", right_char, left_char));
-                                self.cursor_pos += 1;
-                                self.redraw(prompt)?;
+                        } if self.cursor_pos > 0 && self.cursor_pos < self.buffer.chars().count() => {
+                            let left_idx = self.byte_index_at_char_pos(self.cursor_pos - 1);
+                            let right_idx = self.byte_index_at_char_pos(self.cursor_pos);
+                            let left_char = self.buffer.chars().nth(self.cursor_pos - 1).unwrap();
+                            let right_char = self.buffer.chars().nth(self.cursor_pos).unwrap();
+
+                            self.buffer.replace_range(left_idx..right_idx, &format!("{}{}", right_char, left_char));
+                            self.cursor_pos += 1;
+                            self.redraw(prompt)?;
+                        }
+
+                        // Terminals send Ctrl+_ and Ctrl+/ as 0x1F, which crossterm reports as Ctrl+7.
+                        KeyEvent {
+                            code: KeyCode::Char('_') | KeyCode::Char('/') | KeyCode::Char('7'),
+                            modifiers,
+                            ..
+                        } if modifiers.contains(KeyModifiers::CONTROL) => {
+                            is_undo = true;
+                            match self.undo_stack.pop() {
+                                Some((buffer, cursor_pos)) => {
+                                    self.buffer = buffer;
+                                    self.cursor_pos = cursor_pos;
+                                    self.redraw(prompt)?;
+                                }
+                                None => execute!(stdout, Print("\x07"))?,
                             }
                         }
 
@@ -277,74 +609,238 @@ impl LineEditor {
                             modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
                             ..
                         } => {
-                            self.buffer.insert(
-                                self.buffer
-                                    .char_indices()
-                                    .nth(self.cursor_pos)
-                                    .map(|(i, _)| i)
-                                    .unwrap_or(self.buffer.len()),
-                                c,
-                            );
+                            self.buffer.insert(self.byte_index_at_char_pos(self.cursor_pos), c);
                             self.cursor_pos += 1;
                             self.redraw(prompt)?;
                         }
 
                         _ => {}
                     }
+
+                    if !is_undo && self.buffer != snapshot.0 && !(is_insert && self.coalesce_insert) {
+                        self.undo_stack.push(snapshot);
+                    }
+                    self.coalesce_insert = is_insert && !is_undo;
                 }
             }
+        }
+    }
+
+    /// Insert pasted `text` at the cursor as one edit. Pasted newlines stay in
+    /// the buffer, where they separate commands, instead of running the line;
+    /// terminals send them as `\r`.
+    fn paste(&mut self, text: &str, prompt: &str) -> io::Result<()> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.undo_stack.push((self.buffer.clone(), self.cursor_pos));
+        self.coalesce_insert = false;
+        self.last_yank = None;
+        self.history_prefix = None;
+        self.history_completion = None;
+
+        self.buffer.insert_str(self.byte_index_at_char_pos(self.cursor_pos), &text);
+        self.cursor_pos += text.chars().count();
+        self.redraw(prompt)
+    }
+
+    /// Ctrl+R incremental search. Typing refines the query, Ctrl+R steps to the
+    /// next older match, Enter runs the match and Ctrl+C/Escape restore the buffer.
+    fn reverse_search(&mut self, history: &History) -> io::Result<SearchOutcome> {
+        let original = (self.buffer.clone(), self.cursor_pos);
+        let entries = history.entries();
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        let mut failed = false;
+
+        loop {
+            let label = if failed { "(failed reverse-i-search)" } else { "(reverse-i-search)" };
+            self.cursor_pos = found
+                .and_then(|i| entries[i].command.find(&query).map(|b| entries[i].command[..b].chars().count()))
+                .unwrap_or(0);
+            self.redraw(&format!("{}'{}': ", label, query))?;
+
+            let key_event = match event::read()? {
+                Event::Key(key_event) => key_event,
+                _ => continue,
+            };
+
+            let search_from = match key_event {
+                KeyEvent {
+                    code: KeyCode::Char('r'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => found.unwrap_or(entries.len()),
+
+                KeyEvent {
+                    code: KeyCode::Char('c') | KeyCode::Char('g'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                }
+                | KeyEvent {
+                    code: KeyCode::Esc,
+                    ..
+                } => {
+                    (self.buffer, self.cursor_pos) = original;
+                    return Ok(SearchOutcome::Cancel);
+                }
+
+                KeyEvent {
+                    code: KeyCode::Char(c),
+                    modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                    ..
+                } => {
+                    query.push(c);
+                    // The current match may still contain the longer query.
+                    found.map(|i| i + 1).unwrap_or(entries.len())
+                }
+
+                KeyEvent {
+                    code: KeyCode::Backspace,
+                    ..
+                } => {
+                    query.pop();
+                    entries.len()
+                }
+
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                } => {
+                    self.cursor_pos = self.buffer.chars().count();
+                    return Ok(SearchOutcome::Execute);
+                }
 
-            if ctrl_c_pressed {
+                _ => {
+                    self.cursor_pos = self.buffer.chars().count();
+                    return Ok(SearchOutcome::Accept);
+                }
+            };
+
+            if query.is_empty() {
+                failed = false;
                 continue;
             }
+            match history.search_reverse(&query, search_from) {
+                Some(i) => {
+                    found = Some(i);
+                    self.buffer = entries[i].command.clone();
+                    failed = false;
+                }
+                None => failed = true,
+            }
         }
     }
 
-    fn redraw(&self, prompt: &str) -> io::Result<()> {
+    /// Repaint the prompt and buffer. The text may wrap past the terminal width or
+    /// contain newlines, so every row from the prompt's first row down is cleared.
+    fn redraw(&mut self, prompt: &str) -> io::Result<()> {
         let mut stdout = io::stdout();
+        self.path_cache.refresh();
+        let text = highlight(&self.buffer, |name| self.is_command(name));
 
+        if self.cursor_row > 0 {
+            execute!(stdout, cursor::MoveUp(self.cursor_row as u16))?;
+        }
         execute!(
             stdout,
             cursor::MoveToColumn(0),
-            terminal::Clear(ClearType::UntilNewLine),
-            Print(prompt),
-            Print(&self.buffer),
+            terminal::Clear(ClearType::FromCursorDown),
+            Print(prompt.replace('\n', "\r\n")),
+            Print(text.replace('\n', "\r\n")),
         )?;
 
-        let visual_prompt_len = Self::visual_length(prompt);
-        let total_chars = visual_prompt_len + self.cursor_pos;
-        execute!(stdout, cursor::MoveToColumn(total_chars as u16))?;
-        stdout.flush()?;
-        Ok(())
+        let width = Self::terminal_width();
+        let end_chars = self.buffer.chars().count();
+        let (end_row, end_col) = Self::layout(prompt, &self.buffer, end_chars, width);
+        // A row filled to the last column leaves the terminal waiting to wrap;
+        // force the new row so the cursor can be placed at its start.
+        if end_col == 0 && end_row > 0 && !self.buffer.ends_with('\n') {
+            execute!(stdout, Print("\r\n"))?;
+        }
+        self.cursor_row = end_row;
+
+        self.update_cursor_position(prompt)
+    }
+
+    /// Whether `name` runs something: a builtin, an alias, a program on
+    /// `$PATH` or, when it has a `/`, an executable file.
+    fn is_command(&self, name: &str) -> bool {
+        if name.contains('/') {
+            return is_executable(Path::new(&expand_tilde(name)));
+        }
+        builtins::find(name).is_some()
+            || self.aliases.iter().any(|alias| alias == name)
+            || self.path_cache.commands().binary_search_by(|command| command.as_str().cmp(name)).is_ok()
     }
 
-    fn update_cursor_position(&self, prompt: &str) -> io::Result<()> {
+    fn update_cursor_position(&mut self, prompt: &str) -> io::Result<()> {
         let mut stdout = io::stdout();
-        let visual_prompt_len = Self::visual_length(prompt);
-        let total_chars = visual_prompt_len + self.cursor_pos;
-        execute!(stdout, cursor::MoveToColumn(total_chars as u16))?;
+        let width = Self::terminal_width();
+        let (row, col) = Self::layout(prompt, &self.buffer, self.cursor_pos, width);
+
+        if row < self.cursor_row {
+            execute!(stdout, cursor::MoveUp((self.cursor_row - row) as u16))?;
+        } else if row > self.cursor_row {
+            execute!(stdout, cursor::MoveDown((row - self.cursor_row) as u16))?;
+        }
+        execute!(stdout, cursor::MoveToColumn(col as u16))?;
+        self.cursor_row = row;
         stdout.flush()?;
         Ok(())
     }
 
-    fn visual_length(s: &str) -> usize {
-        let mut in_escape = false;
-        let mut length = 0;
+    /// Move below the end of the input so output starts on a fresh line.
+    fn finish_line(&mut self, prompt: &str) -> io::Result<()> {
+        let cursor_pos = self.cursor_pos;
+        self.cursor_pos = self.buffer.chars().count();
+        self.update_cursor_position(prompt)?;
+        self.cursor_pos = cursor_pos;
+        execute!(io::stdout(), Print("\r\n"))?;
+        self.cursor_row = 0;
+        Ok(())
+    }
 
-        for c in s.chars() {
-            if c == '\x1b' {
-                in_escape = true;
-                continue;
-            }
-            if in_escape {
-                if c == 'm' {
-                    in_escape = false;
+    fn terminal_width() -> usize {
+        match terminal::size() {
+            Ok((cols, _)) if cols > 0 => cols as usize,
+            _ => 80,
+        }
+    }
+
+    /// Row and column (relative to the start of the prompt) where the char at
+    /// `chars` in `buffer` is drawn, wrapping at `width` columns.
+    fn layout(prompt: &str, buffer: &str, chars: usize, width: usize) -> (usize, usize) {
+        let mut row = 0;
+        let mut col = 0;
+
+        let prompt_lines: Vec<&str> = prompt.split('\n').collect();
+        let text = prompt_lines[prompt_lines.len() - 1].to_string();
+        row += prompt_lines.len() - 1;
+        for line in &prompt_lines[..prompt_lines.len() - 1] {
+            row += display_width(line) / width;
+        }
+        col += display_width(&text);
+        row += col / width;
+        col %= width;
+
+        for c in buffer.chars().take(chars) {
+            if c == '\n' {
+                row += 1;
+                col = 0;
+            } else {
+                let char_width = c.width().unwrap_or(0);
+                // A wide char that doesn't fit on the row starts the next one.
+                if col + char_width > width {
+                    row += 1;
+                    col = 0;
+                }
+                col += char_width;
+                if col == width {
+                    row += 1;
+                    col = 0;
                 }
-                continue;
             }
-            length += 1;
         }
-        length
+        (row, col)
     }
 
     fn handle_tab_completion(&mut self, prompt: &str) -> io::Result<bool> {
@@ -354,15 +850,73 @@ impl LineEditor {
             .unwrap_or(0);
 
         let token_start_char = self.buffer[..token_start].chars().count();
-        let token = &self.buffer[token_start..self.byte_index_at_char_pos(self.cursor_pos)];
+        let token = self.buffer[token_start..self.byte_index_at_char_pos(self.cursor_pos)].to_string();
+        let token = token.as_str();
+
+        let line = self.buffer[..self.byte_index_at_char_pos(self.cursor_pos)].to_string();
+        let command = line.split_whitespace().next().unwrap_or("");
+        let completer = self.completers.get(command).filter(|_| token_start > 0);
+
+        if token.is_empty() && completer.is_none() {
+            if token_start == 0 && env::var_os("RSHELL_COMPLETE_EMPTY").is_some() {
+                self.list_all_commands(prompt)?;
+            }
+            return Ok(false);
+        }
+        let options = MatchOptions::from_env();
+
+        if let Some(name) = token.strip_prefix('$').filter(|name| !name.contains('/')) {
+            let (open, close, name) = match name.strip_prefix('{') {
+                Some(name) => ("${", "}", name),
+                None => ("$", "", name),
+            };
+            let matches = list_env_vars(name);
+            let completion = match matches.len() {
+                0 => return Ok(false),
+                1 => format!("{}{}{}", open, matches[0], close),
+                _ => {
+                    self.show_completions(&matches, prompt)?;
+                    format!("{}{}", open, common_prefix(&matches, false))
+                }
+            };
+
+            if completion.len() > token.len() {
+                self.buffer.drain(token_start..self.byte_index_at_char_pos(self.cursor_pos));
+                self.buffer.insert_str(token_start, &completion);
+                self.cursor_pos = token_start_char + completion.chars().count();
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        // A bare `~` lists the home directory.
+        let token = if token == "~" { "~/" } else { token };
+
+        if let Some(completer) = completer {
+            let matches = completer.complete(&line, token);
+            if matches.is_empty() {
+                return Ok(false);
+            }
+
+            if matches.len() > 1 {
+                self.show_completions(&matches, prompt)?;
+            }
+
+            let common = common_prefix(&matches, options.ignore_case);
+            if common.len() > token.len() {
+                self.buffer.drain(token_start..self.byte_index_at_char_pos(self.cursor_pos));
+                self.buffer.insert_str(token_start, &common);
+                self.cursor_pos = token_start_char + common.chars().count();
+                return Ok(true);
+            }
 
-        if token.is_empty() {
             return Ok(false);
         }
 
         if token.contains('/') {
             if let Some((dir, prefix)) = split_dir_prefix(token) {
-                let matches = list_dir_matches(&dir, &prefix)?;
+                // List the expanded directory but keep the `~` as typed in the buffer.
+                let matches = list_dir_matches(&expand_tilde(&dir), &prefix, options)?;
                 if matches.is_empty() {
                     return Ok(false);
                 }
@@ -371,7 +925,7 @@ impl LineEditor {
                     self.show_completions(&matches, prompt)?;
                 }
 
-                let common = common_prefix(&matches);
+                let common = common_prefix(&matches, options.ignore_case);
 
                 if common.len() > prefix.len() {
                     self.buffer.drain(token_start..self.byte_index_at_char_pos(self.cursor_pos));
@@ -403,7 +957,16 @@ impl LineEditor {
         } else {
             let is_first = token_start == 0;
             if is_first {
-                let matches = list_path_commands(token)?;
+                self.refresh_path_cache();
+                let mut matches = self.path_cache.matching(token, options);
+                matches.extend(
+                    builtins::BUILTINS
+                        .iter()
+                        .map(|b| b.name.to_string())
+                        .filter(|name| options.matches(name, token)),
+                );
+                matches.sort();
+                matches.dedup();
                 if matches.is_empty() {
                     return Ok(false);
                 }
@@ -412,7 +975,7 @@ impl LineEditor {
                     self.show_completions(&matches, prompt)?;
                 }
 
-                let common = common_prefix(&matches);
+                let common = common_prefix(&matches, options.ignore_case);
                 if common.len() > token.len() {
                     self.buffer.drain(token_start..self.byte_index_at_char_pos(self.cursor_pos));
                     self.buffer.insert_str(token_start, &common);
@@ -430,7 +993,7 @@ impl LineEditor {
 
                 return Ok(false);
             } else {
-                let matches = list_dir_matches(".", token)?;
+                let matches = list_dir_matches(".", token, options)?;
                 if matches.is_empty() {
                     return Ok(false);
                 }
@@ -439,7 +1002,7 @@ impl LineEditor {
                     self.show_completions(&matches, prompt)?;
                 }
 
-                let common = common_prefix(&matches);
+                let common = common_prefix(&matches, options.ignore_case);
                 if common.len() > token.len() {
                     self.buffer.drain(token_start..self.byte_index_at_char_pos(self.cursor_pos));
                     self.buffer.insert_str(token_start, &common);
@@ -461,25 +1024,273 @@ impl LineEditor {
         Ok(false)
     }
 
-    fn show_completions(&self, matches: &[String], prompt: &str) -> io::Result<()> {
+    fn show_completions(&mut self, matches: &[String], prompt: &str) -> io::Result<()> {
+        self.finish_line(prompt)?;
+        if self.confirm_listing(matches.len())? {
+            self.print_matches(matches, prompt)
+        } else {
+            self.redraw(prompt)
+        }
+    }
+
+    /// Print `matches` in columns sized to the terminal, filled top to bottom
+    /// like `ls`, then redraw the prompt below them. Directories are blue.
+    fn print_matches(&mut self, matches: &[String], prompt: &str) -> io::Result<()> {
+        let cell_width = matches.iter().map(|m| display_width(m)).max().unwrap_or(0) + COLUMN_GAP;
+        // The last column needs no gap after it.
+        let (cols, rows) = grid_layout(matches.len(), cell_width, Self::terminal_width() + COLUMN_GAP);
+
         let mut stdout = io::stdout();
+        for row in 0..rows {
+            let mut line = String::new();
+            for col in 0..cols {
+                let Some(name) = matches.get(col * rows + row) else {
+                    break;
+                };
+                if col > 0 {
+                    line.push_str(&" ".repeat(cell_width - display_width(&matches[(col - 1) * rows + row])));
+                }
+                if name.ends_with('/') {
+                    line.push_str(&name.blue().to_string());
+                } else {
+                    line.push_str(name);
+                }
+            }
+            queue!(stdout, Print(line), Print("\r\n"))?;
+        }
+        stdout.flush()?;
+
+        self.redraw(prompt)
+    }
 
-        execute!(stdout, cursor::MoveToColumn(0))?;
-        execute!(stdout, terminal::Clear(terminal::ClearType::CurrentLine))?;
+    /// Tab on an empty line lists every builtin and PATH command when
+    /// `RSHELL_COMPLETE_EMPTY` is set.
+    fn list_all_commands(&mut self, prompt: &str) -> io::Result<()> {
+        self.refresh_path_cache();
+        let mut commands = self.path_cache.matching("", MatchOptions::default());
+        commands.extend(builtins::BUILTINS.iter().map(|b| b.name.to_string()));
+        commands.sort();
+        commands.dedup();
 
-        if !matches.is_empty() {
-            let output = matches.join("    ");
-            println!("{}", output);
+        self.show_completions(&commands, prompt)
+    }
+
+    /// Ask before listing a large number of completions. Expects the cursor at
+    /// the start of a fresh line and leaves it at the start of the next one.
+    fn confirm_listing(&mut self, count: usize) -> io::Result<bool> {
+        if count <= COMPLETION_QUERY_ITEMS {
+            return Ok(true);
         }
 
-        print!("\r\n{}{}", prompt, &self.buffer);
+        let mut stdout = io::stdout();
+        execute!(stdout, Print(format!("Display all {} possibilities? (y or n)", count)))?;
+        let confirmed = loop {
+            if let Event::Key(key_event) = event::read()? {
+                break matches!(key_event.code, KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Char(' '));
+            }
+        };
+        execute!(stdout, Print("\r\n"))?;
+        Ok(confirmed)
+    }
 
-        let visual_prompt_len = Self::visual_length(prompt);
-        let total_chars = visual_prompt_len + self.cursor_pos;
-        execute!(stdout, cursor::MoveToColumn(total_chars as u16))?;
+    /// Run a vi command-mode key. Unknown keys beep.
+    fn vi_command(&mut self, c: char, prompt: &str, history: &mut History) -> io::Result<()> {
+        let len = self.buffer.chars().count();
+        match c {
+            'h' => self.cursor_pos = self.cursor_pos.saturating_sub(1),
+            'l' if self.cursor_pos + 1 < len => self.cursor_pos += 1,
+            '0' | '^' => self.cursor_pos = 0,
+            '$' => self.cursor_pos = len.saturating_sub(1),
+            'w' => self.cursor_pos = self.next_word_start().min(len.saturating_sub(1)),
+            'b' => self.cursor_pos = self.previous_word_start(),
+            'i' => self.vi_command_mode = false,
+            'a' => {
+                self.cursor_pos = (self.cursor_pos + 1).min(len);
+                self.vi_command_mode = false;
+            }
+            'I' => {
+                self.cursor_pos = 0;
+                self.vi_command_mode = false;
+            }
+            'A' => {
+                self.cursor_pos = len;
+                self.vi_command_mode = false;
+            }
+            'x' if self.cursor_pos < len => {
+                let removed = self.buffer.remove(self.byte_index_at_char_pos(self.cursor_pos));
+                self.push_kill(removed.to_string());
+                self.cursor_pos = self.cursor_pos.min(len.saturating_sub(2));
+            }
+            'X' if self.cursor_pos > 0 => {
+                self.cursor_pos -= 1;
+                let removed = self.buffer.remove(self.byte_index_at_char_pos(self.cursor_pos));
+                self.push_kill(removed.to_string());
+            }
+            'D' | 'C' => {
+                let killed = self.buffer.split_off(self.byte_index_at_char_pos(self.cursor_pos));
+                self.push_kill(killed);
+                if c == 'C' {
+                    self.vi_command_mode = false;
+                } else {
+                    self.cursor_pos = self.cursor_pos.saturating_sub(1);
+                }
+            }
+            'S' => {
+                let killed = std::mem::take(&mut self.buffer);
+                self.push_kill(killed);
+                self.cursor_pos = 0;
+                self.vi_command_mode = false;
+            }
+            'p' | 'P' => match self.kill_ring.last().cloned() {
+                Some(text) => {
+                    if c == 'p' {
+                        self.cursor_pos = (self.cursor_pos + 1).min(len);
+                    }
+                    self.yank(&text);
+                    self.cursor_pos -= 1;
+                }
+                None => execute!(io::stdout(), Print("\x07"))?,
+            },
+            'u' => match self.undo_stack.pop() {
+                Some((buffer, cursor_pos)) => {
+                    self.buffer = buffer;
+                    self.cursor_pos = cursor_pos.min(self.buffer.chars().count().saturating_sub(1));
+                }
+                None => execute!(io::stdout(), Print("\x07"))?,
+            },
+            'k' | 'j' => {
+                let entry = if c == 'k' { history.previous() } else { history.next() };
+                if let Some(entry) = entry {
+                    self.buffer = entry.clone();
+                    self.cursor_pos = 0;
+                }
+            }
+            _ => execute!(io::stdout(), Print("\x07"))?,
+        }
+        self.redraw(prompt)
+    }
 
-        stdout.flush()?;
-        Ok(())
+    /// Char position of the start of the next word after the cursor.
+    fn next_word_start(&self) -> usize {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut pos = self.cursor_pos;
+        while pos < chars.len() && !chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Char position just past the end of the word after the cursor, or of
+    /// the word the cursor is in.
+    fn next_word_end(&self) -> usize {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut pos = self.cursor_pos;
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        while pos < chars.len() && !chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Char position of the start of the word before the cursor.
+    fn previous_word_start(&self) -> usize {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut pos = self.cursor_pos;
+        while pos > 0 && chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        while pos > 0 && !chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// Kill from the start of the word before the cursor up to the cursor,
+    /// blanks after the word included (Ctrl+W).
+    fn kill_word_backward(&mut self, previous: Option<KillDirection>) {
+        let start = self.previous_word_start();
+        let range = self.byte_index_at_char_pos(start)..self.byte_index_at_char_pos(self.cursor_pos);
+        let killed: String = self.buffer.drain(range).collect();
+        self.cursor_pos = start;
+        self.kill(killed, KillDirection::Backward, previous);
+    }
+
+    /// Kill from the cursor to the end of the next word, blanks before the
+    /// word included (Alt+D).
+    fn kill_word_forward(&mut self, previous: Option<KillDirection>) {
+        let range = self.byte_index_at_char_pos(self.cursor_pos)..self.byte_index_at_char_pos(self.next_word_end());
+        let killed: String = self.buffer.drain(range).collect();
+        self.kill(killed, KillDirection::Forward, previous);
+    }
+
+    /// Change the case of the text from the cursor to the end of the word and
+    /// move past it. Case changes can change the length, as `ß` uppercases
+    /// to `SS`, so the cursor moves by the changed text.
+    fn change_word_case(&mut self, case: WordCase) {
+        let range = self.byte_index_at_char_pos(self.cursor_pos)..self.byte_index_at_char_pos(self.next_word_end());
+        let word = &self.buffer[range.clone()];
+        let changed = match case {
+            WordCase::Upper => word.to_uppercase(),
+            WordCase::Lower => word.to_lowercase(),
+            WordCase::Capitalize => {
+                let mut changed = String::new();
+                let mut first = true;
+                for c in word.chars() {
+                    if c.is_whitespace() {
+                        changed.push(c);
+                    } else if std::mem::take(&mut first) {
+                        changed.extend(c.to_uppercase());
+                    } else {
+                        changed.extend(c.to_lowercase());
+                    }
+                }
+                changed
+            }
+        };
+        self.cursor_pos += changed.chars().count();
+        self.buffer.replace_range(range, &changed);
+    }
+
+    /// Put `text` in the kill ring. When the previous key killed in the same
+    /// direction, it joins that entry instead, so Ctrl+Y brings back
+    /// everything killed in a row: after it going forward, before it going
+    /// backward.
+    fn kill(&mut self, text: String, direction: KillDirection, previous: Option<KillDirection>) {
+        if text.is_empty() {
+            self.last_kill = previous;
+            return;
+        }
+        self.last_kill = Some(direction);
+        match self.kill_ring.last_mut() {
+            Some(last) if previous == Some(direction) => match direction {
+                KillDirection::Forward => last.push_str(&text),
+                KillDirection::Backward => last.insert_str(0, &text),
+            },
+            _ => self.push_kill(text),
+        }
+    }
+
+    fn push_kill(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        self.kill_ring.push(text);
+        if self.kill_ring.len() > KILL_RING_MAX {
+            self.kill_ring.remove(0);
+        }
+    }
+
+    /// Insert `text` at the cursor and remember where it went for Alt+Y.
+    fn yank(&mut self, text: &str) {
+        let start = self.cursor_pos;
+        self.buffer.insert_str(self.byte_index_at_char_pos(start), text);
+        self.cursor_pos = start + text.chars().count();
+        self.last_yank = Some((start, text.chars().count()));
     }
 
     fn byte_index_at_char_pos(&self, char_pos: usize) -> usize {
@@ -490,3 +1301,115 @@ impl LineEditor {
             .unwrap_or(self.buffer.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_width_of_wide_chars() {
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("\x1b[32m$\x1b[0m 日"), 4);
+    }
+
+    #[test]
+    fn test_layout_with_wide_chars() {
+        let buffer = "日本語";
+        assert_eq!(LineEditor::layout("$ ", buffer, 0, 80), (0, 2));
+        assert_eq!(LineEditor::layout("$ ", buffer, 2, 80), (0, 6));
+        assert_eq!(LineEditor::layout("$ ", buffer, 3, 80), (0, 8));
+        // The second char doesn't fit in the last column and wraps.
+        assert_eq!(LineEditor::layout("$ ", buffer, 1, 5), (0, 4));
+        assert_eq!(LineEditor::layout("$ ", buffer, 2, 5), (1, 2));
+    }
+
+    #[test]
+    fn test_byte_index_with_multibyte_buffer() {
+        let mut editor = LineEditor::new();
+        editor.buffer = "日本語".to_string();
+        assert_eq!(editor.byte_index_at_char_pos(1), 3);
+        assert_eq!(editor.byte_index_at_char_pos(3), editor.buffer.len());
+
+        editor.buffer.remove(editor.byte_index_at_char_pos(1));
+        assert_eq!(editor.buffer, "日語");
+    }
+
+    #[test]
+    fn test_emacs_word_motions() {
+        let mut editor = LineEditor::new();
+        editor.buffer = "  echo   héllo ".to_string();
+        let ends: Vec<usize> = [0, 2, 4, 6, 7, 14, 15]
+            .iter()
+            .map(|&pos| {
+                editor.cursor_pos = pos;
+                editor.next_word_end()
+            })
+            .collect();
+        assert_eq!(ends, [6, 6, 6, 14, 14, 15, 15]);
+
+        let starts: Vec<usize> = [15, 14, 11, 9, 8, 2, 0]
+            .iter()
+            .map(|&pos| {
+                editor.cursor_pos = pos;
+                editor.previous_word_start()
+            })
+            .collect();
+        assert_eq!(starts, [9, 9, 9, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_word_kills_join_in_kill_ring() {
+        let mut editor = LineEditor::new();
+        editor.buffer = "git  commit -m msg  ".to_string();
+        editor.cursor_pos = 3;
+        editor.kill_word_forward(None);
+        editor.kill_word_forward(editor.last_kill);
+        assert_eq!(editor.buffer, "git msg  ");
+        assert_eq!(editor.kill_ring, ["  commit -m"]);
+
+        editor.cursor_pos = 7;
+        editor.kill_word_forward(None);
+        editor.kill_word_forward(editor.last_kill);
+        assert_eq!(editor.buffer, "git msg");
+        assert_eq!(editor.kill_ring, ["  commit -m", "  "]);
+
+        editor.kill_word_backward(None);
+        editor.kill_word_backward(editor.last_kill);
+        editor.kill_word_backward(editor.last_kill);
+        assert_eq!(editor.buffer, "");
+        assert_eq!(editor.kill_ring, ["  commit -m", "  ", "git msg"]);
+    }
+
+    #[test]
+    fn test_word_case() {
+        let mut editor = LineEditor::new();
+        editor.buffer = "echo hELLO wORLD".to_string();
+        editor.cursor_pos = 4;
+        editor.change_word_case(WordCase::Capitalize);
+        editor.change_word_case(WordCase::Upper);
+        assert_eq!(editor.buffer, "echo Hello WORLD");
+        assert_eq!(editor.cursor_pos, 16);
+
+        editor.buffer = "éCOLE straße".to_string();
+        editor.cursor_pos = 0;
+        editor.change_word_case(WordCase::Lower);
+        assert_eq!((editor.buffer.as_str(), editor.cursor_pos), ("école straße", 5));
+        editor.change_word_case(WordCase::Upper);
+        assert_eq!((editor.buffer.as_str(), editor.cursor_pos), ("école STRASSE", 13));
+        editor.cursor_pos = 0;
+        editor.change_word_case(WordCase::Capitalize);
+        assert_eq!((editor.buffer.as_str(), editor.cursor_pos), ("École STRASSE", 5));
+    }
+
+    #[test]
+    fn test_vi_word_motions() {
+        let mut editor = LineEditor::new();
+        editor.buffer = "echo  hello world".to_string();
+        editor.cursor_pos = 1;
+        assert_eq!(editor.next_word_start(), 6);
+        editor.cursor_pos = 8;
+        assert_eq!(editor.previous_word_start(), 6);
+        editor.cursor_pos = 6;
+        assert_eq!(editor.previous_word_start(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::command::{Command, TokenKind};
+use crate::variables;
+use colored::{Color, Colorize};
+use std::ops::Range;
+
+/// Operators after which a new command starts.
+const COMMAND_SEPARATORS: &[&str] = &["|", "|&", "&&", "||", "&"];
+
+/// Colors for the parts of `line`, as byte ranges: the command word of each
+/// command green when `is_command` knows it and red otherwise, quoted text
+/// yellow and operators cyan. Uses the parser's tokenizer, so a `|` in quotes
+/// isn't colored as a pipe. A command word with quotes or `$` in it isn't
+/// checked, as it is only known once expanded.
+pub fn highlight_spans(line: &str, is_command: impl Fn(&str) -> bool) -> Vec<(Range<usize>, Color)> {
+    let tokens = Command::tokenize(line);
+    let mut spans = Vec::new();
+    let mut command_position = true;
+    let mut redirect_target = false;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let (kind, range) = &tokens[i];
+        if *kind == TokenKind::Operator {
+            spans.push((range.clone(), Color::Cyan));
+            let operator = &line[range.clone()];
+            if COMMAND_SEPARATORS.contains(&operator) {
+                command_position = true;
+            } else {
+                redirect_target = true;
+            }
+            i += 1;
+            continue;
+        }
+
+        // The pieces of one word touch each other.
+        let mut end = i + 1;
+        while end < tokens.len()
+            && tokens[end].0 != TokenKind::Operator
+            && tokens[end].1.start == tokens[end - 1].1.end
+        {
+            end += 1;
+        }
+        let word = &tokens[i..end];
+        let word_range = range.start..word[word.len() - 1].1.end;
+        let text = &line[word_range.clone()];
+        i = end;
+
+        if redirect_target {
+            redirect_target = false;
+        } else if command_position && !is_assignment(text) {
+            // `time` runs the command after it.
+            command_position = text == "time";
+            if word.iter().all(|(kind, _)| *kind == TokenKind::Word) && !text.contains('$') {
+                spans.push((word_range, if is_command(text) { Color::Green } else { Color::Red }));
+                continue;
+            }
+        }
+        let quoted = word.iter().filter(|(kind, _)| *kind == TokenKind::Quoted);
+        spans.extend(quoted.map(|(_, range)| (range.clone(), Color::Yellow)));
+    }
+    spans
+}
+
+/// `line` with the colors of `highlight_spans` applied. Colors are left out
+/// when `colored` is told not to use them, as with `NO_COLOR`.
+pub fn highlight(line: &str, is_command: impl Fn(&str) -> bool) -> String {
+    if !colored::control::SHOULD_COLORIZE.should_colorize() {
+        return line.to_string();
+    }
+    let mut result = String::new();
+    let mut pos = 0;
+    for (range, color) in highlight_spans(line, is_command) {
+        result.push_str(&line[pos..range.start]);
+        result.push_str(&line[range.clone()].color(color).to_string());
+        pos = range.end;
+    }
+    result.push_str(&line[pos..]);
+    result
+}
+
+/// Whether `word` is a `NAME=value` assignment, which comes before the
+/// command word rather than being it.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| variables::is_valid_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_spans() {
+        let line = "FOO=1 ls 'a|b' > out | nosuch && time \"$X\"";
+        let spans: Vec<(&str, Color)> = highlight_spans(line, |name| name == "ls")
+            .into_iter()
+            .map(|(range, color)| (&line[range], color))
+            .collect();
+
+        assert_eq!(
+            spans,
+            [
+                ("ls", Color::Green),
+                ("'a|b'", Color::Yellow),
+                (">", Color::Cyan),
+                ("|", Color::Cyan),
+                ("nosuch", Color::Red),
+                ("&&", Color::Cyan),
+                ("time", Color::Red),
+                ("\"$X\"", Color::Yellow),
+            ]
+        );
+    }
+}
@@ -1,5 +1,8 @@
+mod completer;
 mod completion;
 mod core;
+mod highlight;
 mod raw_mode;
 
-pub use core::LineEditor;
+pub use completion::grid_layout;
+pub use core::{EditMode, LineEditor};
@@ -1,9 +1,13 @@
 use std::io;
 use std::sync::Once;
-use crossterm::terminal;
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::{execute, terminal};
 
 static SET_PANIC_HOOK: Once = Once::new();
 
+/// Raw mode with bracketed paste, so pasted text arrives as one
+/// `Event::Paste` instead of keys that would run each pasted line. Both are
+/// turned off again when the guard is dropped.
 pub struct RawModeGuard;
 
 impl RawModeGuard {
@@ -12,18 +16,22 @@ impl RawModeGuard {
         SET_PANIC_HOOK.call_once(|| {
             let prev = std::panic::take_hook();
             std::panic::set_hook(Box::new(move |info| {
+                let _ = execute!(io::stdout(), DisableBracketedPaste);
                 let _ = terminal::disable_raw_mode();
                 prev(info);
             }));
         });
 
         terminal::enable_raw_mode()?;
-        Ok(Self)
+        let guard = Self;
+        execute!(io::stdout(), EnableBracketedPaste)?;
+        Ok(guard)
     }
 }
 
 impl Drop for RawModeGuard {
     fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableBracketedPaste);
         let _ = terminal::disable_raw_mode();
     }
 }
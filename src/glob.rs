@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::Path;
+
+/// Expand `*`, `?` and `[...]` patterns in each argument against the filesystem.
+/// Arguments without wildcards, or patterns that match nothing, are kept as-is.
+pub fn expand_globs(args: Vec<String>) -> Vec<String> {
+    let mut expanded = Vec::new();
+
+    for arg in args {
+        if !has_wildcards(&arg) {
+            expanded.push(arg);
+            continue;
+        }
+
+        let mut matches = expand_pattern(&arg);
+        if matches.is_empty() {
+            expanded.push(arg);
+        } else {
+            matches.sort();
+            expanded.extend(matches);
+        }
+    }
+
+    expanded
+}
+
+fn has_wildcards(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+fn expand_pattern(pattern: &str) -> Vec<String> {
+    let root = if pattern.starts_with('/') { "/" } else { "" };
+    let mut candidates = vec![root.to_string()];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let mut next = Vec::new();
+
+        for base in &candidates {
+            let join = |name: &str| {
+                if base.is_empty() || base.ends_with('/') {
+                    format!("{}{}", base, name)
+                } else {
+                    format!("{}/{}", base, name)
+                }
+            };
+
+            if !has_wildcards(component) {
+                next.push(join(component));
+                continue;
+            }
+
+            let dir = if base.is_empty() { "." } else { base.as_str() };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Like bash, wildcards don't match a leading dot unless the pattern has one.
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if matches_pattern(component, &name) {
+                    next.push(join(&name));
+                }
+            }
+        }
+
+        candidates = next;
+    }
+
+    candidates.retain(|c| Path::new(c).exists());
+    candidates
+}
+
+/// Match a single path component against a shell wildcard pattern.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), parse_class(&pattern[1..])) {
+            (Some(&c), Some((matched, consumed))) => {
+                matched(c) && match_from(&pattern[1 + consumed..], &name[1..])
+            }
+            // An unterminated class is a literal '['.
+            (Some('['), None) => match_from(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(&c) => name.first() == Some(&c) && match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Parse a `[...]` class body (after the opening bracket). Returns a predicate and
+/// the number of pattern chars consumed, including the closing bracket.
+fn parse_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let start = i;
+    while i < pattern.len() {
+        let c = pattern[i];
+        // A ']' right after the opening bracket is a literal member.
+        if c == ']' && i > start {
+            let consumed = i + 1;
+            let matched = move |ch: char| {
+                let hit = ranges.iter().any(|&(lo, hi)| lo <= ch && ch <= hi);
+                hit != negated
+            };
+            return Some((matched, consumed));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            ranges.push((c, pattern[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*.txt", "notes.txt"));
+        assert!(!matches_pattern("*.txt", "notes.rs"));
+        assert!(matches_pattern("?.rs", "a.rs"));
+        assert!(!matches_pattern("?.rs", "ab.rs"));
+        assert!(matches_pattern("[ab]*", "beta"));
+        assert!(!matches_pattern("[!ab]*", "beta"));
+        assert!(matches_pattern("file[0-9]", "file7"));
+    }
+
+    #[test]
+    fn test_expand_globs_in_directory() {
+        let dir = TempDir::new("glob");
+        for name in ["a.txt", "b.txt", "c.rs", ".hidden.txt"] {
+            fs::File::create(dir.join(name)).unwrap();
+        }
+        let base = dir.display().to_string();
+
+        assert_eq!(
+            expand_globs(vec![format!("{}/*.txt", base)]),
+            vec![format!("{}/a.txt", base), format!("{}/b.txt", base)]
+        );
+        assert_eq!(expand_globs(vec![format!("{}/?.rs", base)]), vec![format!("{}/c.rs", base)]);
+        assert_eq!(
+            expand_globs(vec![format!("{}/[bc].*", base)]),
+            vec![format!("{}/b.txt", base), format!("{}/c.rs", base)]
+        );
+
+        let literal = format!("{}/*.md", base);
+        assert_eq!(expand_globs(vec![literal.clone()]), vec![literal]);
+    }
+}
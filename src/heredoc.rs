@@ -1,98 +1,185 @@
-use std::io::{self, Write};
-use std::fs::File;
-
-pub fn parse_heredoc(input: &str) -> Option<(String, String, bool)> {
-    if let Some(pos) = input.find("<<") {
-        let before = input[..pos].trim();
-        let after = input[pos + 2..].trim();
-        
-        let mut parts = after.splitn(2, char::is_whitespace);
-        let delimiter = parts.next()?.trim();
-        
-        let quoted = (delimiter.starts_with('\'') && delimiter.ends_with('\'')) ||
-                     (delimiter.starts_with('"') && delimiter.ends_with('"'));
-        
-        let delimiter = if quoted {
-            &delimiter[1..delimiter.len()-1]
-        } else {
-            delimiter
-        };
-        
-        return Some((before.to_string(), delimiter.to_string(), quoted));
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+use crate::command::Command as ShellCommand;
+use crate::jobs;
+use crate::redirects::{self, ParsedCommand};
+
+/// A heredoc redirect parsed from a command line.
+#[derive(Debug, PartialEq)]
+pub struct Heredoc {
+    /// The command with its other redirects, e.g. `cat > out`.
+    pub command: String,
+    pub delimiter: String,
+    /// The delimiter was quoted (`<<'EOF'`), so the body isn't expanded.
+    pub quoted: bool,
+    /// `<<-`: leading tabs are stripped from the body and delimiter lines.
+    pub strip_tabs: bool,
+}
+
+/// Split `cmd << EOF > out` into the command with its other redirects
+/// (`cmd > out`), the delimiter, and its flags.
+pub fn parse_heredoc(input: &str) -> Option<Heredoc> {
+    let pos = ShellCommand::split_unquoted(input, &["<<"]).first()?.1.len();
+    let before = input[..pos].trim();
+    let after = input.get(pos + 2..)?;
+    let strip_tabs = after.starts_with('-');
+    let after = after.strip_prefix('-').unwrap_or(after).trim_start();
+
+    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+    let (delimiter, rest) = after.split_at(end);
+    if delimiter.is_empty() {
+        return None;
     }
-    None
+
+    let quoted = delimiter.len() >= 2
+        && ((delimiter.starts_with('\'') && delimiter.ends_with('\''))
+            || (delimiter.starts_with('"') && delimiter.ends_with('"')));
+    let delimiter = if quoted {
+        &delimiter[1..delimiter.len() - 1]
+    } else {
+        delimiter
+    };
+
+    Some(Heredoc {
+        command: format!("{} {}", before, rest.trim()).trim().to_string(),
+        delimiter: delimiter.to_string(),
+        quoted,
+        strip_tabs,
+    })
 }
 
-pub fn read_heredoc_lines(delimiter: &str) -> io::Result<Vec<String>> {
+/// Read body lines from `input` up to a line that is exactly `delimiter`,
+/// prompting with `> ` when `prompt` is set. With `strip_tabs`, leading tabs
+/// are removed from every line first. End of input also ends the body, with
+/// a warning as in other shells. Lines after the delimiter are left unread.
+pub fn read_heredoc_lines(
+    input: &mut impl Iterator<Item = io::Result<String>>,
+    delimiter: &str,
+    strip_tabs: bool,
+    prompt: bool,
+) -> io::Result<Vec<String>> {
     let mut lines = Vec::new();
-    let stdin = io::stdin();
-    
+
     loop {
-        print!("> ");
-        io::stdout().flush()?;
-        
-        let mut line = String::new();
-        stdin.read_line(&mut line)?;
-        
-        let trimmed = line.trim_end_matches('\n');
-        if trimmed == delimiter {
+        if prompt {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+
+        let Some(line) = input.next().transpose()? else {
+            eprintln!("warning: here-document delimited by end-of-file (wanted `{}')", delimiter);
+            break;
+        };
+
+        let mut line = line.as_str();
+        if strip_tabs {
+            line = line.trim_start_matches('\t');
+        }
+        if line == delimiter {
             break;
         }
-        lines.push(line);
+        lines.push(line.to_string());
     }
-    
+
     Ok(lines)
 }
 
-pub fn execute_heredoc(command: &str, delimiter: &str, _quoted: bool) -> io::Result<()> {
-    use std::process::{Command, Stdio};
-    use std::io::Write;
-    
-    let lines = read_heredoc_lines(delimiter)?;
-    let content = lines.join("");
-    
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return Ok(());
+/// Join the body lines. Unless the delimiter was quoted, `$NAME`, `$(...)`
+/// and backticks in the body are expanded, and a backslash keeps a following
+/// `$`, backtick or backslash literal.
+pub fn heredoc_body(lines: &[String], quoted: bool) -> Result<String, String> {
+    let mut body = lines.join("\n");
+    if !lines.is_empty() {
+        body.push('\n');
     }
-    
-    let has_redirect_out = command.contains('>');
-    let (cmd_part, outfile) = if has_redirect_out {
-        if let Some(pos) = command.find('>') {
-            let cmd = command[..pos].trim();
-            let file = command[pos+1..].trim();
-            (cmd, Some(file))
-        } else {
-            (command, None)
-        }
+    if quoted {
+        Ok(body)
     } else {
-        (command, None)
-    };
-    
-    let cmd_parts: Vec<&str> = cmd_part.split_whitespace().collect();
-    if cmd_parts.is_empty() {
-        return Ok(());
+        ShellCommand::expand_text(&body)
     }
-    
-    let program = cmd_parts[0];
-    let args = &cmd_parts[1..];
-    
-    let mut child = Command::new(program)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(if outfile.is_some() { Stdio::piped() } else { Stdio::inherit() })
-        .spawn()?;
-    
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(content.as_bytes())?;
+}
+
+/// Read the body of `heredoc` from stdin and run its command with the body
+/// on its stdin. Returns the command's exit status.
+pub fn execute_heredoc(heredoc: &Heredoc) -> io::Result<i32> {
+    let stdin = io::stdin();
+    let prompt = io::IsTerminal::is_terminal(&stdin);
+    run_heredoc(heredoc, &mut stdin.lock().lines(), prompt)
+}
+
+/// Read the body of `heredoc` from `input`, such as the lines of a script
+/// after it, and run its command with the body on its stdin.
+pub fn run_heredoc(
+    heredoc: &Heredoc,
+    input: &mut impl Iterator<Item = io::Result<String>>,
+    prompt: bool,
+) -> io::Result<i32> {
+    let lines = read_heredoc_lines(input, &heredoc.delimiter, heredoc.strip_tabs, prompt)?;
+
+    let parsed = ParsedCommand::parse(&heredoc.command).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if parsed.program.is_empty() {
+        return Ok(0);
+    }
+
+    let body = heredoc_body(&lines, heredoc.quoted).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut cmd = Command::new(&parsed.program);
+    cmd.args(&parsed.args);
+    parsed.apply_redirects(&mut cmd, [Some(redirects::text_stream(body)?), None, None])?;
+    Ok(jobs::exit_code(cmd.status()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse_heredoc() {
+        let heredoc = |command: &str, delimiter: &str, quoted, strip_tabs| Heredoc {
+            command: command.to_string(),
+            delimiter: delimiter.to_string(),
+            quoted,
+            strip_tabs,
+        };
+        assert_eq!(parse_heredoc("cat << EOF > out.txt"), Some(heredoc("cat > out.txt", "EOF", false, false)));
+        assert_eq!(parse_heredoc("cat <<'END'"), Some(heredoc("cat", "END", true, false)));
+        assert_eq!(parse_heredoc("cat <<-EOF"), Some(heredoc("cat", "EOF", false, true)));
+        assert_eq!(parse_heredoc("cat <<- 'EOF'"), Some(heredoc("cat", "EOF", true, true)));
+        assert_eq!(parse_heredoc("cat <<"), None);
+    }
+
+    #[test]
+    fn test_heredoc_body_expansion() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let lines = vec!["home: $HOME".to_string(), "cost: \\$5".to_string()];
+        assert_eq!(heredoc_body(&lines, false).unwrap(), format!("home: {}\ncost: $5\n", home));
+        assert_eq!(heredoc_body(&lines, true).unwrap(), "home: $HOME\ncost: \\$5\n");
+        assert_eq!(heredoc_body(&[], false).unwrap(), "");
+    }
+
+    #[test]
+    fn test_cat_heredoc_expands_home() {
+        let dir = TempDir::new("heredoc");
+        let out = dir.join("out");
+        let heredoc = parse_heredoc(&format!("cat <<EOF > {}", out.display())).unwrap();
+        run_heredoc(&heredoc, &mut io::Cursor::new("dir=$HOME\nEOF\n").lines(), false).unwrap();
+        let written = std::fs::read_to_string(&out).unwrap_or_default();
+
+        assert_eq!(written, format!("dir={}\n", std::env::var("HOME").unwrap_or_default()));
     }
-    
-    let output = child.wait_with_output()?;
-    
-    if let Some(file) = outfile {
-        let mut f = File::create(file)?;
-        f.write_all(&output.stdout)?;
+
+    #[test]
+    fn test_read_heredoc_lines_stops_at_delimiter() {
+        let mut input = io::Cursor::new("one\n  EOF\ntwo\nEOF\nafter\n").lines();
+        let lines = read_heredoc_lines(&mut input, "EOF", false, false).unwrap();
+        assert_eq!(lines, vec!["one", "  EOF", "two"]);
+        assert_eq!(input.next().unwrap().unwrap(), "after");
+
+        let mut unterminated = io::Cursor::new("only\n").lines();
+        assert_eq!(read_heredoc_lines(&mut unterminated, "EOF", false, false).unwrap(), vec!["only"]);
+
+        let mut indented = io::Cursor::new("\t\tone\n\t  two\n\tEOF\n").lines();
+        assert_eq!(read_heredoc_lines(&mut indented, "EOF", true, false).unwrap(), vec!["one", "  two"]);
     }
-    
-    Ok(())
 }
@@ -1,98 +1,471 @@
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::env;
+use crate::command::expand_tilde;
+
+/// Entries kept when `HISTSIZE` is unset or not a number.
+const DEFAULT_HISTSIZE: usize = 1000;
+
+/// What `add` keeps, from `HISTSIZE` and `HISTCONTROL`. Read on every add so
+/// values set in `~/.rshellrc` or at the prompt take effect.
+struct Settings {
+    /// How many entries to keep.
+    size: usize,
+    /// Skip commands typed with a leading space (`ignorespace`).
+    ignore_space: bool,
+    /// Remove earlier copies of a command when it is added (`erasedups`).
+    erase_dups: bool,
+    /// Store when each command was entered (`HISTTIMEFORMAT` is set).
+    record_time: bool,
+}
+
+impl Settings {
+    /// `HISTCONTROL` is a colon-separated list of `ignorespace`, `ignoredups`,
+    /// `ignoreboth` and `erasedups`. A repeat of the previous command is
+    /// always skipped, so `ignoredups` needs no handling of its own.
+    fn from_env() -> Self {
+        let control = env::var("HISTCONTROL").unwrap_or_default();
+        let has = |name: &str| control.split(':').any(|item| item == name);
+        Settings {
+            size: env::var("HISTSIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_HISTSIZE),
+            ignore_space: has("ignorespace") || has("ignoreboth"),
+            erase_dups: has("erasedups"),
+            record_time: env::var_os("HISTTIMEFORMAT").is_some(),
+        }
+    }
+}
+
+/// A command in the history, with the time it was entered when that was
+/// recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// Seconds since the Unix epoch. Only recorded while `HISTTIMEFORMAT` is
+    /// set, as in bash.
+    pub timestamp: Option<u64>,
+}
 
 pub struct History {
-    commands: Vec<String>,
+    commands: Vec<HistoryEntry>,
     file_path: PathBuf,
     position: usize,
+    /// Entries in the history file, which is appended to and only trimmed
+    /// once it holds twice as many entries as are kept.
+    file_lines: usize,
 }
 
 impl History {
     pub fn new() -> Self {
-        let file_path = Self::get_history_path();
-        let commands = Self::load_from_file(&file_path);
+        Self::open(Self::get_history_path())
+    }
+
+    fn open(file_path: PathBuf) -> Self {
+        let mut commands = Self::load_from_file(&file_path);
+        let file_lines = commands.len();
+        let limit = Settings::from_env().size;
+        if commands.len() > limit {
+            commands.drain(..commands.len() - limit);
+        }
         let position = commands.len();
-        
+
         Self {
             commands,
             file_path,
             position,
+            file_lines,
         }
     }
-    
+
+    /// `$RSHELL_HISTFILE`, else `$HISTFILE`, else `~/.rshell_history`. A
+    /// relative path is taken from the directory the shell started in. The
+    /// default file starts as a copy of `~/.mycli_history`, the name older
+    /// versions used, when only that one exists.
     fn get_history_path() -> PathBuf {
-        let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        PathBuf::from(home).join(".mycli_history")
+        if let Some(path) = ["RSHELL_HISTFILE", "HISTFILE"]
+            .iter()
+            .find_map(|name| env::var(name).ok().filter(|v| !v.is_empty()))
+        {
+            let path = PathBuf::from(expand_tilde(&path));
+            return env::current_dir().map(|cwd| cwd.join(&path)).unwrap_or(path);
+        }
+
+        let home = PathBuf::from(env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+        let path = home.join(".rshell_history");
+        let legacy = home.join(".mycli_history");
+        if !path.exists() && legacy.exists() {
+            let _ = fs::copy(&legacy, &path);
+        }
+        path
     }
     
-    fn load_from_file(path: &PathBuf) -> Vec<String> {
-        if let Ok(file) = File::open(path) {
-            let reader = BufReader::new(file);
-            reader.lines()
-                .filter_map(|line| line.ok())
-                .collect()
-        } else {
-            Vec::new()
+    /// Read the entries in `path`. A `#<seconds>` line, as bash writes with
+    /// `HISTTIMEFORMAT` set, is the timestamp of the command after it.
+    fn load_from_file(path: &PathBuf) -> Vec<HistoryEntry> {
+        let Ok(file) = File::open(path) else {
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        let mut timestamp = None;
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            if let Some(secs) = line.strip_prefix('#').and_then(|t| t.parse().ok()) {
+                timestamp = Some(secs);
+                continue;
+            }
+            entries.push(HistoryEntry { command: line, timestamp: timestamp.take() });
         }
+        entries
     }
     
-    pub fn add(&mut self, command: String) {
-        if command.trim().is_empty() {
+    /// Add `line` as it was typed; surrounding whitespace is trimmed.
+    pub fn add(&mut self, line: &str) {
+        self.add_with(line, &Settings::from_env());
+    }
+
+    /// Add `line` following `settings`, then drop the oldest entries beyond
+    /// the size limit.
+    fn add_with(&mut self, line: &str, settings: &Settings) {
+        let command = line.trim();
+        if command.is_empty() || (settings.ignore_space && line.starts_with(' ')) {
             return;
         }
-        
+
+        let mut rewrite = false;
+        if settings.erase_dups {
+            let before = self.commands.len();
+            self.commands.retain(|entry| entry.command != command);
+            rewrite = self.commands.len() != before;
+        }
+
         // Don't add duplicate of last command
-        if self.commands.last() != Some(&command) {
-            self.commands.push(command.clone());
-            self.save_to_file(&command);
+        if self.commands.last().map(|entry| entry.command.as_str()) != Some(command) {
+            let timestamp = settings
+                .record_time
+                .then(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+            let entry = HistoryEntry { command: command.to_string(), timestamp };
+            self.save_to_file(&entry);
+            self.commands.push(entry);
+            self.file_lines += 1;
+        }
+
+        if self.commands.len() > settings.size {
+            self.commands.drain(..self.commands.len() - settings.size);
         }
-        
+        if rewrite || self.file_lines > settings.size.saturating_mul(2) {
+            self.rewrite_file();
+        }
+
         self.position = self.commands.len();
     }
+
+    /// Replace the history file with the entries kept in memory.
+    fn rewrite_file(&mut self) {
+        let contents: String = self.commands.iter().map(Self::file_lines).collect();
+        if fs::write(&self.file_path, contents).is_ok() {
+            self.file_lines = self.commands.len();
+        }
+    }
     
-    fn save_to_file(&self, command: &str) {
+    fn save_to_file(&self, entry: &HistoryEntry) {
         if let Ok(mut file) = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.file_path)
         {
-            let _ = writeln!(file, "{}", command);
+            let _ = write!(file, "{}", Self::file_lines(entry));
+        }
+    }
+
+    /// `entry` as it is stored in the history file, preceded by its timestamp.
+    fn file_lines(entry: &HistoryEntry) -> String {
+        match entry.timestamp {
+            Some(secs) => format!("#{}\n{}\n", secs, entry.command),
+            None => format!("{}\n", entry.command),
         }
     }
     
     pub fn previous(&mut self) -> Option<&String> {
         if self.position > 0 {
             self.position -= 1;
-            self.commands.get(self.position)
+            self.commands.get(self.position).map(|entry| &entry.command)
         } else {
             None
         }
     }
     
     pub fn next(&mut self) -> Option<&String> {
-        if self.position < self.commands.len() - 1 {
+        if self.position + 1 < self.commands.len() {
             self.position += 1;
-            Some(&self.commands[self.position])
+            Some(&self.commands[self.position].command)
         } else {
             self.position = self.commands.len();
             None
         }
     }
     
-    pub fn list(&self) {
-        for (i, cmd) in self.commands.iter().enumerate() {
-            println!("{}: {}", i + 1, cmd);
+    /// Move back to the closest older entry that starts with `prefix`.
+    pub fn previous_with_prefix(&mut self, prefix: &str) -> Option<&String> {
+        let found = self.commands[..self.position.min(self.commands.len())]
+            .iter()
+            .rposition(|entry| entry.command.starts_with(prefix))?;
+        self.position = found;
+        self.commands.get(found).map(|entry| &entry.command)
+    }
+
+    /// Move forward to the closest newer entry that starts with `prefix`,
+    /// or past the end of history (returning `None`) if there is none.
+    pub fn next_with_prefix(&mut self, prefix: &str) -> Option<&String> {
+        let start = (self.position + 1).min(self.commands.len());
+        match self.commands[start..].iter().position(|entry| entry.command.starts_with(prefix)) {
+            Some(offset) => {
+                self.position = start + offset;
+                self.commands.get(self.position).map(|entry| &entry.command)
+            }
+            None => {
+                self.position = self.commands.len();
+                None
+            }
         }
     }
 
+    /// Write the entries with their history numbers, as `history` shows them.
+    /// With `HISTTIMEFORMAT` set, each is preceded by its time in that
+    /// `strftime` format, e.g. `%F %T `.
+    pub fn list(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let time_format = env::var("HISTTIMEFORMAT").ok();
+        for (i, entry) in self.commands.iter().enumerate() {
+            let time = match (&time_format, entry.timestamp) {
+                (Some(format), Some(secs)) => format_time(secs, format),
+                _ => String::new(),
+            };
+            writeln!(out, "{}: {}{}", i + 1, time, entry.command)?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.commands
+    }
+
+    /// Index of the newest entry before `before` that contains `query`.
+    pub fn search_reverse(&self, query: &str, before: usize) -> Option<usize> {
+        self.commands[..before.min(self.commands.len())]
+            .iter()
+            .rposition(|entry| entry.command.contains(query))
+    }
+
+    /// The distinct entries that start with `prefix` and add something to it,
+    /// newest first, for completing a line in place.
+    pub fn history_completions(&self, prefix: &str) -> Vec<String> {
+        let mut completions: Vec<String> = Vec::new();
+        for entry in self.commands.iter().rev() {
+            if entry.command.len() > prefix.len()
+                && entry.command.starts_with(prefix)
+                && !completions.contains(&entry.command)
+            {
+                completions.push(entry.command.clone());
+            }
+        }
+        completions
+    }
+
+    /// Expand history references in `input` like bash: `!!` is the previous
+    /// command, `!N` entry N as `history` numbers it, `!-N` the Nth previous
+    /// command and `!prefix` the newest command starting with `prefix`. A `!`
+    /// inside single quotes, after a backslash, or before a blank, `=` or `(`
+    /// is left alone. A `'` inside double quotes doesn't start single quotes.
+    pub fn expand(&self, input: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut chars = input.char_indices().peekable();
+        let mut in_single_quotes = false;
+        let mut in_double_quotes = false;
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+                '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+                '\\' if !in_single_quotes => {
+                    result.push(c);
+                    if let Some((_, next)) = chars.next() {
+                        result.push(next);
+                    }
+                    continue;
+                }
+                '!' if !in_single_quotes && !result.ends_with(['$', '[']) => {
+                    let rest = &input[i + 1..];
+                    let word_len = rest.find(|c: char| c.is_whitespace() || ";&|<>()'\"".contains(c)).unwrap_or(rest.len());
+                    let event = match rest.chars().next() {
+                        Some('!') => "!",
+                        Some(c) if c.is_whitespace() || c == '=' || c == '(' => "",
+                        _ => &rest[..word_len],
+                    };
+                    if !event.is_empty() {
+                        result.push_str(self.event(event).ok_or_else(|| format!("!{}: event not found", event))?);
+                        for _ in 0..event.chars().count() {
+                            chars.next();
+                        }
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            result.push(c);
+        }
+
+        Ok(result)
+    }
+
+    /// The command a history reference after `!` names: `!`, a number, a
+    /// negative number or a prefix.
+    fn event(&self, event: &str) -> Option<&str> {
+        let commands = &self.commands;
+        let entry = if event == "!" {
+            commands.last()
+        } else if let Ok(n) = event.parse::<isize>() {
+            match n {
+                1.. => commands.get(n as usize - 1),
+                ..=-1 => commands.len().checked_sub(n.unsigned_abs()).and_then(|i| commands.get(i)),
+                0 => None,
+            }
+        } else {
+            commands.iter().rev().find(|entry| entry.command.starts_with(event))
+        };
+        entry.map(|entry| entry.command.as_str())
+    }
+
     #[allow(dead_code)]
     pub fn search(&self, pattern: &str) -> Vec<(usize, &String)> {
         self.commands
             .iter()
+            .map(|entry| &entry.command)
             .enumerate()
             .filter(|(_, cmd)| cmd.contains(pattern))
             .collect()
     }
 }
+
+/// `secs` since the epoch in local time, formatted with `strftime`.
+fn format_time(secs: u64, format: &str) -> String {
+    #[cfg(unix)]
+    {
+        let Ok(format) = std::ffi::CString::new(format) else {
+            return String::new();
+        };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let t = secs as libc::time_t;
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return String::new();
+        }
+        let mut buf = [0u8; 256];
+        let len = unsafe { libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm) };
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = format;
+        format!("{} ", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_history_size_limit() {
+        let dir = TempDir::new("history");
+        let path = dir.join("history");
+        let mut history = History::open(path.clone());
+        let settings = Settings { size: 10, ignore_space: false, erase_dups: false, record_time: false };
+        for i in 0..25 {
+            history.add_with(&format!("cmd {}", i), &settings);
+        }
+        let file = fs::read_to_string(&path).unwrap_or_default();
+        let reopened = History::open(path.clone()).entries().len();
+
+        assert_eq!(history.entries().len(), 10);
+        assert_eq!(history.entries()[0].command, "cmd 15");
+        assert!(file.lines().count() <= 20);
+        assert!(file.ends_with("cmd 24\n"));
+        assert!(reopened <= 20);
+
+        assert_eq!(history.previous().map(String::as_str), Some("cmd 24"));
+        assert_eq!(history.next(), None);
+        history.add_with("last", &Settings { size: 0, ..settings });
+        assert_eq!(history.previous(), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn test_history_control() {
+        let dir = TempDir::new("histcontrol");
+        let path = dir.join("history");
+        let mut history = History::open(path.clone());
+        let settings = Settings { size: 100, ignore_space: true, erase_dups: true, record_time: false };
+        for line in ["ls", "pwd", "ls", " secret", "  ", "cd /tmp ", "pwd", "pwd"] {
+            history.add_with(line, &settings);
+        }
+        let file = fs::read_to_string(&path).unwrap_or_default();
+
+        let commands: Vec<&str> = history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, ["ls", "cd /tmp", "pwd"]);
+        assert_eq!(file, "ls\ncd /tmp\npwd\n");
+    }
+
+    #[test]
+    fn test_history_completions() {
+        let dir = TempDir::new("histcomp");
+        let path = dir.join("history");
+        let mut history = History::open(path.clone());
+        let settings = Settings { size: 100, ignore_space: false, erase_dups: false, record_time: false };
+        for line in ["git status", "ls", "git log", "git", "git status"] {
+            history.add_with(line, &settings);
+        }
+
+        assert_eq!(history.history_completions("git"), ["git status", "git log"]);
+        assert_eq!(history.history_completions("git l"), ["git log"]);
+        assert!(history.history_completions("cargo").is_empty());
+    }
+
+    #[test]
+    fn test_history_timestamps() {
+        let dir = TempDir::new("histtime");
+        let path = dir.join("history");
+        fs::write(&path, "old\n#1700000000\nstamped\n").unwrap();
+        let mut history = History::open(path.clone());
+        let settings = Settings { size: 100, ignore_space: false, erase_dups: false, record_time: true };
+        history.add_with("new", &settings);
+        let file = fs::read_to_string(&path).unwrap_or_default();
+        let reopened = History::open(path.clone()).entries().to_vec();
+
+        assert_eq!(reopened[0], HistoryEntry { command: "old".to_string(), timestamp: None });
+        assert_eq!(reopened[1], HistoryEntry { command: "stamped".to_string(), timestamp: Some(1700000000) });
+        assert!(reopened[2].timestamp.is_some_and(|secs| secs > 1700000000));
+        assert!(file.lines().nth(3).is_some_and(|line| line.starts_with('#')));
+        assert_eq!(format_time(1700000000, "%Y "), "2023 ");
+    }
+
+    #[test]
+    fn test_history_expansion() {
+        let dir = TempDir::new("histexpand");
+        let path = dir.join("history");
+        let mut history = History::open(path.clone());
+        let settings = Settings { size: 100, ignore_space: false, erase_dups: false, record_time: false };
+        for command in ["ls -l", "echo one", "cd /tmp"] {
+            history.add_with(command, &settings);
+        }
+
+        assert_eq!(history.expand("sudo !!").unwrap(), "sudo cd /tmp");
+        assert_eq!(history.expand("!1 | wc").unwrap(), "ls -l | wc");
+        assert_eq!(history.expand("!-2").unwrap(), "echo one");
+        assert_eq!(history.expand("!ec && !l").unwrap(), "echo one && ls -l");
+        assert_eq!(history.expand("echo '!!' \\!! ! x!=y").unwrap(), "echo '!!' \\!! ! x!=y");
+        assert_eq!(history.expand("echo $! [!a]").unwrap(), "echo $! [!a]");
+        assert_eq!(history.expand("echo \"don't\" && !!").unwrap(), "echo \"don't\" && cd /tmp");
+        assert_eq!(history.expand("echo '\"' !-1").unwrap(), "echo '\"' cd /tmp");
+        assert_eq!(history.expand("!foo").unwrap_err(), "!foo: event not found");
+        assert!(history.expand("!9").is_err());
+    }
+}
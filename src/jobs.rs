@@ -1,8 +1,10 @@
 use std::collections::HashMap;
-use std::process::Child;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::signal_handler::SignalHandler;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-#[allow(dead_code)]
 pub enum JobStatus {
     Running,
     Stopped,
@@ -12,16 +14,183 @@ pub enum JobStatus {
 #[derive(Debug)]
 pub struct Job {
     pub id: u32,
+    /// The job's first process, which leads its process group.
     pub pid: u32,
+    /// The process group shared by every process in the job.
+    pub pgid: u32,
     pub command: String,
     pub status: JobStatus,
-    pub process: Option<Child>,
+    /// Every process in the job; a pipeline has one per stage.
+    pub processes: Vec<Child>,
 }
 
+/// How a job left the foreground.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForegroundOutcome {
+    /// All processes finished; holds the exit code of the last one.
+    Exited(i32),
+    /// The last process was killed by this signal.
+    Killed(i32),
+    /// The job was suspended, e.g. with Ctrl-Z.
+    Stopped,
+}
+
+impl ForegroundOutcome {
+    /// Exit status as `$?` reports it: 128 plus the signal for a killed job,
+    /// and 128 + SIGTSTP for a stopped one.
+    pub fn status(self) -> i32 {
+        match self {
+            ForegroundOutcome::Exited(code) => code,
+            ForegroundOutcome::Killed(signal) => 128 + signal,
+            ForegroundOutcome::Stopped => 148,
+        }
+    }
+}
+
+/// Exit status of a finished process in the same form as [`ForegroundOutcome::status`].
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Start the child in process group `pgid`, or in a new group of its own when
+/// `pgid` is `None`, so Ctrl-Z and `fg` act on the whole job.
+pub fn set_process_group(cmd: &mut Command, pgid: Option<u32>) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        cmd.process_group(pgid.map_or(0, |pgid| pgid as i32));
+        // Undo signal_handler::ignore_job_control_signals so Ctrl-Z stops the child.
+        unsafe {
+            cmd.pre_exec(|| {
+                for signal in [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (cmd, pgid);
+}
+
+/// Give the terminal to process group `pgid` and wait until its processes
+/// have all exited or the job is stopped. With `resume`, a stopped job is sent
+/// SIGCONT once it owns the terminal, so it can't stop again on SIGTTIN.
+/// With `pipefail`, the outcome is that of the last process to fail rather
+/// than of the last process, as in bash's `set -o pipefail`.
+/// Reaped processes are removed from `processes`, and the terminal is handed
+/// back to the shell afterwards.
+// Processes are reaped with waitpid, which clippy can't see.
+#[allow(clippy::zombie_processes)]
+pub fn wait_foreground(pgid: u32, processes: &mut Vec<Child>, resume: bool, pipefail: bool) -> ForegroundOutcome {
+    let mut outcome = ForegroundOutcome::Exited(0);
+    let mut failure = None;
+
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+        use nix::unistd::{getpgrp, tcsetpgrp, Pid};
+        use std::io::{self, IsTerminal};
+
+        let terminal = io::stdin();
+        let interactive = terminal.is_terminal();
+        if interactive {
+            let _ = tcsetpgrp(&terminal, Pid::from_raw(pgid as i32));
+        }
+        if resume {
+            if let Err(e) = self::resume(pgid) {
+                eprintln!("Error resuming job: {}", e);
+            }
+        }
+
+        while let Some(child) = processes.first() {
+            match waitpid(Pid::from_raw(child.id() as i32), Some(WaitPidFlag::WUNTRACED)) {
+                Ok(WaitStatus::Exited(_, code)) => {
+                    processes.remove(0);
+                    outcome = ForegroundOutcome::Exited(code);
+                    if code != 0 {
+                        failure = Some(outcome);
+                    }
+                }
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    processes.remove(0);
+                    outcome = ForegroundOutcome::Killed(signal as i32);
+                    failure = Some(outcome);
+                }
+                Ok(WaitStatus::Stopped(_, _)) => {
+                    outcome = ForegroundOutcome::Stopped;
+                    break;
+                }
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(_) => {
+                    processes.remove(0);
+                }
+            }
+        }
+
+        if interactive {
+            let _ = tcsetpgrp(&terminal, getpgrp());
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (pgid, resume);
+        for mut child in processes.drain(..) {
+            if let Ok(status) = child.wait() {
+                outcome = ForegroundOutcome::Exited(status.code().unwrap_or(1));
+                if !status.success() {
+                    failure = Some(outcome);
+                }
+            }
+        }
+    }
+
+    match failure {
+        Some(failure) if pipefail && outcome == ForegroundOutcome::Exited(0) => failure,
+        _ => outcome,
+    }
+}
+
+/// Send SIGCONT to every process in group `pgid`.
+pub fn resume(pgid: u32) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{killpg, Signal};
+        use nix::unistd::Pid;
+
+        killpg(Pid::from_raw(pgid as i32), Signal::SIGCONT).map_err(|e| e.to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pgid;
+        Err("Signal handling not supported on this platform".to_string())
+    }
+}
+
+/// How long the shell waits on exit for its jobs to finish, so the ones that
+/// end quickly, or on SIGHUP, are reaped instead of left as zombies.
+const SHUTDOWN_WAIT: Duration = Duration::from_millis(200);
+
 pub struct JobManager {
     jobs: HashMap<u32, Job>,
     next_id: u32,
-    foreground_pid: Option<u32>,
+    /// Jobs removed with `disown`. They are no longer reported, only reaped
+    /// quietly, and listed on exit while still running.
+    disowned: Vec<Job>,
+    /// Messages about jobs that finished or stopped in the background,
+    /// held until the shell is about to show its prompt.
+    notifications: Vec<String>,
+    signal_handler: SignalHandler,
 }
 
 impl JobManager {
@@ -29,32 +198,58 @@ impl JobManager {
         JobManager {
             jobs: HashMap::new(),
             next_id: 1,
-            foreground_pid: None,
+            disowned: Vec::new(),
+            notifications: Vec::new(),
+            signal_handler: SignalHandler::new(),
         }
     }
 
+    /// Record the foreground job's process group; Ctrl+C is forwarded to it.
     pub fn set_foreground_pid(&mut self, pid: Option<u32>) {
-        self.foreground_pid = pid;
+        self.signal_handler.set_child(pid);
     }
 
+    #[allow(dead_code)]
     pub fn get_foreground_pid(&self) -> Option<u32> {
-        self.foreground_pid
+        self.signal_handler.get_child()
+    }
+
+    pub fn add_job(&mut self, pid: u32, command: String, processes: Vec<Child>) -> u32 {
+        let id = self.insert(pid, command, JobStatus::Running, processes);
+        println!("[{}] {}", id, pid);
+        id
+    }
+
+    /// Register a foreground job that was just suspended.
+    pub fn add_stopped_job(&mut self, pid: u32, command: String, processes: Vec<Child>) -> u32 {
+        let id = self.insert(pid, command, JobStatus::Stopped, processes);
+        println!("\n[{}] Stopped {}", id, self.jobs[&id].command);
+        id
     }
 
-    pub fn add_job(&mut self, pid: u32, command: String, process: Child) -> u32 {
+    /// Put back a job taken out with `remove_job` that was stopped again,
+    /// keeping its id.
+    pub fn restore_stopped_job(&mut self, mut job: Job) {
+        println!("\n[{}] Stopped {}", job.id, job.command);
+        job.status = JobStatus::Stopped;
+        self.jobs.insert(job.id, job);
+    }
+
+    fn insert(&mut self, pid: u32, command: String, status: JobStatus, processes: Vec<Child>) -> u32 {
         let id = self.next_id;
         self.next_id += 1;
 
+        // Each job is started in a new process group led by its first process.
         let job = Job {
             id,
             pid,
+            pgid: pid,
             command,
-            status: JobStatus::Running,
-            process: Some(process),
+            status,
+            processes,
         };
 
         self.jobs.insert(id, job);
-        println!("[{}] {}", id, pid);
         id
     }
 
@@ -62,7 +257,7 @@ impl JobManager {
         self.jobs.get(&id)
     }
 
-    pub fn _get_job_mut(&mut self, id: u32) -> Option<&mut Job> {
+    pub fn get_job_mut(&mut self, id: u32) -> Option<&mut Job> {
         self.jobs.get_mut(&id)
     }
 
@@ -70,28 +265,122 @@ impl JobManager {
         self.jobs.remove(&id)
     }
 
+    /// Stop tracking job `id`, or the most recently started job when `id` is
+    /// `None`, without killing it. Returns the id of the disowned job.
+    pub fn disown(&mut self, id: Option<u32>) -> Result<u32, String> {
+        let id = match id {
+            Some(id) => id,
+            None => *self.jobs.keys().max().ok_or("no current job")?,
+        };
+        let job = self.jobs.remove(&id).ok_or_else(|| format!("job {} not found", id))?;
+        self.disowned.push(job);
+        Ok(id)
+    }
+
+    /// Disowned jobs that haven't finished yet.
+    pub fn disowned_jobs(&self) -> &[Job] {
+        &self.disowned
+    }
+
     pub fn list_jobs(&self) -> Vec<&Job> {
         let mut jobs: Vec<&Job> = self.jobs.values().collect();
         jobs.sort_by_key(|j| j.id);
         jobs
     }
 
+    /// Jobs that keep running after the shell exits: after `shutdown`, the
+    /// running jobs that didn't finish in time or ignored SIGHUP.
+    pub fn surviving_jobs(&self) -> Vec<&Job> {
+        self.list_jobs()
+            .into_iter()
+            .filter(|job| job.status == JobStatus::Running)
+            .collect()
+    }
+
+    /// Number of jobs that are still running or stopped.
+    pub fn active_count(&self) -> usize {
+        self.jobs.values().filter(|j| j.status != JobStatus::Done).count()
+    }
+
+    #[allow(clippy::zombie_processes)]
     pub fn update_jobs(&mut self) {
         let mut completed = Vec::new();
 
         for (id, job) in self.jobs.iter_mut() {
-            if let Some(ref mut child) = job.process {
-                match child.try_wait() {
+            if job.processes.is_empty() {
+                continue;
+            }
+
+            // std's try_wait can't see stops, so ask waitpid for stop/continue transitions too.
+            #[cfg(unix)]
+            {
+                use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+                use nix::unistd::Pid;
+
+                let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+                let mut last_exit = None;
+                let mut i = 0;
+                while i < job.processes.len() {
+                    let pid = Pid::from_raw(job.processes[i].id() as i32);
+                    match waitpid(pid, Some(flags)) {
+                        Ok(status @ (WaitStatus::Exited(..) | WaitStatus::Signaled(..))) => {
+                            job.processes.remove(i);
+                            last_exit = Some(status);
+                            continue;
+                        }
+                        Ok(WaitStatus::Stopped(_, _)) => {
+                            if job.status != JobStatus::Stopped {
+                                self.notifications.push(format!("[{}] Stopped {}", id, job.command));
+                            }
+                            job.status = JobStatus::Stopped;
+                        }
+                        Ok(WaitStatus::Continued(_)) => {
+                            job.status = JobStatus::Running;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("Error checking job {}: {}", id, e);
+                        }
+                    }
+                    i += 1;
+                }
+
+                if job.processes.is_empty() {
+                    match last_exit {
+                        Some(WaitStatus::Signaled(_, signal, _)) => {
+                            self.notifications
+                                .push(format!("[{}] Terminated {} ({})", id, job.command, signal.as_str()));
+                        }
+                        Some(WaitStatus::Exited(_, code)) => {
+                            self.notifications.push(format!("[{}] Done {} (exit: {})", id, job.command, code));
+                        }
+                        _ => {}
+                    }
+                    job.status = JobStatus::Done;
+                    completed.push(*id);
+                }
+            }
+
+            #[cfg(not(unix))]
+            {
+                let mut last_exit = None;
+                job.processes.retain_mut(|child| match child.try_wait() {
                     Ok(Some(status)) => {
-                        println!("\n[{}] Done {} (exit: {})", id, job.command, status);
-                        job.status = JobStatus::Done;
-                        job.process = None;
-                        completed.push(*id);
+                        last_exit = Some(status);
+                        false
                     }
-                    Ok(None) => {}
+                    Ok(None) => true,
                     Err(e) => {
                         eprintln!("Error checking job {}: {}", id, e);
+                        true
+                    }
+                });
+                if job.processes.is_empty() {
+                    if let Some(status) = last_exit {
+                        self.notifications.push(format!("[{}] Done {} (exit: {})", id, job.command, status));
                     }
+                    job.status = JobStatus::Done;
+                    completed.push(*id);
                 }
             }
         }
@@ -99,6 +388,49 @@ impl JobManager {
         for id in completed {
             self.jobs.remove(&id);
         }
+
+        for job in &mut self.disowned {
+            job.processes.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        }
+        self.disowned.retain(|job| !job.processes.is_empty());
+    }
+
+    /// Prepare for the shell to exit. With `hangup`, as with bash's
+    /// `huponexit`, every job is sent SIGHUP, and SIGCONT too if it is
+    /// stopped so it can act on it. Then jobs are reaped as they finish, for
+    /// up to `SHUTDOWN_WAIT`; the ones still running are left to run on.
+    pub fn shutdown(&mut self, hangup: bool) {
+        #[cfg(unix)]
+        if hangup {
+            use nix::sys::signal::{killpg, Signal};
+            use nix::unistd::Pid;
+
+            for job in self.jobs.values_mut() {
+                let _ = killpg(Pid::from_raw(job.pgid as i32), Signal::SIGHUP);
+                if job.status == JobStatus::Stopped && resume(job.pgid).is_ok() {
+                    job.status = JobStatus::Running;
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = hangup;
+
+        let deadline = Instant::now() + SHUTDOWN_WAIT;
+        loop {
+            self.update_jobs();
+            let waiting = self.jobs.values().any(|job| job.status == JobStatus::Running) || !self.disowned.is_empty();
+            if !waiting || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// The messages `update_jobs` collected about background jobs that
+    /// finished or stopped since the last call, oldest first. They are held
+    /// back so they don't land in the middle of a foreground command's output.
+    pub fn take_completed_notifications(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notifications)
     }
 
     pub fn _find_job_by_pid(&self, pid: u32) -> Option<u32> {
@@ -107,3 +439,66 @@ impl JobManager {
             .map(|j| j.id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disown_most_recent_job() {
+        let mut manager = JobManager::new();
+        for _ in 0..2 {
+            let child = Command::new("sleep").arg("5").spawn().unwrap();
+            manager.add_job(child.id(), "sleep 5".to_string(), vec![child]);
+        }
+
+        assert_eq!(manager.disown(None), Ok(2));
+        assert_eq!(manager.disown(Some(7)), Err("job 7 not found".to_string()));
+        assert_eq!(manager.disown(None), Ok(1));
+        assert_eq!(manager.disown(None), Err("no current job".to_string()));
+        assert_eq!(manager.disowned_jobs().len(), 2);
+
+        for job in &mut manager.disowned {
+            for child in &mut job.processes {
+                child.kill().unwrap();
+                child.wait().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_completed_notifications_are_buffered() {
+        let mut manager = JobManager::new();
+        let child = Command::new("true").spawn().unwrap();
+        manager.add_job(child.id(), "true".to_string(), vec![child]);
+
+        for _ in 0..200 {
+            manager.update_jobs();
+            if manager.list_jobs().is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(manager.take_completed_notifications(), ["[1] Done true (exit: 0)"]);
+        assert!(manager.take_completed_notifications().is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_hangs_up_jobs() {
+        let mut manager = JobManager::new();
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        set_process_group(&mut sleep, None);
+        let child = sleep.spawn().unwrap();
+        manager.add_job(child.id(), "sleep 5".to_string(), vec![child]);
+
+        manager.shutdown(false);
+        let survivors = manager.surviving_jobs().len();
+        manager.shutdown(true);
+
+        assert_eq!(survivors, 1);
+        assert!(manager.surviving_jobs().is_empty());
+        assert_eq!(manager.take_completed_notifications(), ["[1] Terminated sleep 5 (SIGHUP)"]);
+    }
+}
@@ -1,25 +1,37 @@
 use std::env;
+use std::fs;
 use std::process;
 use std::time::Instant;
 
+mod builtins;
 mod command;
 mod prompt;
 mod history;
-mod editor;  // Changed from 'input' to 'editor'
+mod editor;
 mod shell;
 mod variables;
 mod jobs;
 mod pipes;
 mod redirects;
 mod heredoc;
+mod glob;
 mod signal_handler;
+mod suggest;
+#[cfg(test)]
+mod test_util;
 
 fn print_help() {
     println!("rshell - custom shell");
     println!();
-    println!("Usage: rshell [OPTIONS]");
+    println!("Usage: rshell [OPTIONS] [SCRIPT]");
+    println!("       rshell [OPTIONS] -c COMMAND");
     println!("  -h, --help       Print this help");
     println!("  -v, --version    Print version");
+    println!("  -c COMMAND       Run COMMAND and exit with its status");
+    println!("  -q, --quiet      Don't print the startup banner");
+    println!("      --norc       Don't run ~/.rshellrc");
+    println!("      --no-state   Don't restore or save aliases and variables in ~/.rshell_state");
+    println!("      --debug      Print startup timings to stderr (or set RSHELL_DEBUG=1)");
 }
 
 fn print_version() {
@@ -42,9 +54,34 @@ fn main() {
     let start = Instant::now();
 
     let mut shell = shell::Shell::new();
+    shell.set_quiet(args.iter().any(|a| a == "-q" || a == "--quiet"));
+    shell.set_norc(args.iter().any(|a| a == "--norc"));
+    shell.set_no_state(args.iter().any(|a| a == "--no-state"));
 
-    eprintln!("DEBUG: Startup took {:?}", start.elapsed());
+    let debug = args.iter().any(|a| a == "--debug") || env::var("RSHELL_DEBUG").is_ok_and(|v| !v.is_empty() && v != "0");
+    shell.set_debug(debug);
+    if debug {
+        eprintln!("DEBUG: Startup took {:?}", start.elapsed());
+    }
 
-    shell.run();
+    // Options come first; the first other argument is a script to run.
+    let operands: Vec<&String> = args[1..].iter().skip_while(|a| a.starts_with('-') && *a != "-c").collect();
+    match operands.first().map(|a| a.as_str()) {
+        Some("-c") => match operands.get(1) {
+            Some(command) => process::exit(shell.run_script("rshell: -c", command)),
+            None => {
+                eprintln!("rshell: -c: option requires an argument");
+                process::exit(2);
+            }
+        },
+        Some(path) => match fs::read_to_string(path) {
+            Ok(source) => process::exit(shell.run_script(path, &source)),
+            Err(e) => {
+                eprintln!("rshell: {}: {}", path, e);
+                process::exit(127);
+            }
+        },
+        None => shell.run(),
+    }
 
 }
@@ -1,62 +1,269 @@
-use std::process::{Command, Stdio};
-use std::io;
-
-/// Parse user input into pipeline commands
-/// e.g., "ls -l | grep rshell | wc -l" -> Vec<Vec<String>>
-pub fn parse_pipeline(input: &str) -> Vec<Vec<String>> {
-    input
-        .split('|')
-        .map(|cmd| cmd.trim()
-                    .split_whitespace()
-                    .map(|s| s.to_string())
-                    .collect())
-        .collect()
-}
+use std::fs::File;
+use std::process::{Child, Command};
+use std::io::{self, Write};
+use std::thread;
+use crate::command::{join_for_display, Command as ShellCommand};
+use crate::jobs::{self, ForegroundOutcome, JobManager};
+use crate::redirects::{self, ParsedCommand};
 
-/// Execute a pipeline of commands
-/// Connects stdout of each command to stdin of the next
-pub fn run_pipeline(commands: Vec<Vec<String>>) -> io::Result<()> {
-    if commands.is_empty() {
-        return Ok(());
+/// Parse user input into pipeline stages, each with its own redirects.
+/// e.g., "grep x f | sort > out" -> [grep x f, sort with stdout to out].
+/// A `|` inside quotes or `$(...)` doesn't split. A stage followed by `|&`
+/// pipes its stderr as well, like `2>&1 |`.
+pub fn parse_pipeline(input: &str) -> Result<Vec<ParsedCommand>, String> {
+    let stages = ShellCommand::split_unquoted(input, &["|&", "|"]);
+    let mut commands = Vec::new();
+    for (i, (separator, stage)) in stages.iter().enumerate() {
+        let mut parsed = ParsedCommand::parse(stage)?;
+        if parsed.program.is_empty() {
+            let token = separator.or_else(|| stages.get(i + 1).and_then(|(next, _)| *next));
+            return Err(format!("syntax error near unexpected token `{}'", token.unwrap_or("|")));
+        }
+        parsed.merge_stderr = stages.get(i + 1).is_some_and(|(next, _)| *next == Some("|&"));
+        commands.push(parsed);
     }
+    Ok(commands)
+}
 
-    let mut children = Vec::new();
-    let mut previous_stdout = None;
-
-    for (i, cmd_parts) in commands.iter().enumerate() {
-        if cmd_parts.is_empty() {
-            continue;
-        }
+/// Whether `input` has a `|` outside quotes, i.e. more than one stage.
+/// Conditional `||` has already been split off by the caller.
+pub fn is_pipeline(input: &str) -> bool {
+    ShellCommand::split_unquoted(input, &["|"]).len() > 1
+}
 
-        let mut cmd = Command::new(&cmd_parts[0]);
-        if cmd_parts.len() > 1 {
-            cmd.args(&cmd_parts[1..]);
-        }
+/// Runs a pipeline stage that names a builtin inside the shell, writing its
+/// output to the given writer, and returns its exit status. `None` means the
+/// stage has to be started as an external command instead.
+pub type RunBuiltin<'a> = dyn FnMut(&ParsedCommand, &mut dyn Write, &mut JobManager) -> Option<i32> + 'a;
 
-        if let Some(stdin) = previous_stdout {
-            cmd.stdin(stdin);
-        }
+/// Start a pipeline of commands in one new process group, so job control
+/// treats it as a single job. Connects stdout of each command to stdin of the
+/// next; a stage's own redirects take precedence over the pipe.
+///
+/// Stages that `run_builtin` accepts run in the shell as they are reached.
+/// The output of a builtin in the middle of a pipeline is written to the next
+/// stage's stdin from a separate thread. A command that can't be started is
+/// reported and the others still run, the next one reading an empty pipe.
+/// Returns the started processes and, when the last stage was a builtin or
+/// couldn't be started, its exit status.
+pub fn spawn_pipeline(
+    commands: &[ParsedCommand],
+    job_manager: &mut JobManager,
+    run_builtin: &mut RunBuiltin,
+) -> io::Result<(Vec<Child>, Option<i32>)> {
+    let mut children: Vec<Child> = Vec::new();
+    let mut previous_stdout = None;
 
-        if i < commands.len() - 1 {
-            cmd.stdout(Stdio::piped());
+    for (i, stage) in commands.iter().enumerate() {
+        let last = i == commands.len() - 1;
+        if last {
+            if let Some(status) = run_builtin(stage, &mut io::stdout(), job_manager) {
+                return Ok((children, Some(status)));
+            }
         } else {
-            cmd.stdout(Stdio::inherit());
+            let mut output = Vec::new();
+            if run_builtin(stage, &mut output, job_manager).is_some() {
+                let (reader, mut writer) = match redirects::pipe() {
+                    Ok(pipe) => pipe,
+                    Err(e) => {
+                        reap(children);
+                        return Err(e);
+                    }
+                };
+                // The next stage may stop reading early; the write then just fails.
+                thread::spawn(move || writer.write_all(&output));
+                previous_stdout = Some(reader);
+                continue;
+            }
         }
 
-        let mut child = cmd.spawn()?;
+        let mut cmd = Command::new(&stage.program);
+        cmd.args(&stage.args);
+        jobs::set_process_group(&mut cmd, children.first().map(|leader| leader.id()));
 
-        previous_stdout = if i < commands.len() - 1 {
-            Some(Stdio::from(child.stdout.take().unwrap()))
-        } else {
-            None
+        let mut streams: [Option<File>; 3] = [previous_stdout.take(), None, None];
+        if !last {
+            let pipe = redirects::pipe().and_then(|(reader, writer)| {
+                let stderr = if stage.merge_stderr { Some(writer.try_clone()?) } else { None };
+                Ok((reader, writer, stderr))
+            });
+            match pipe {
+                Ok((reader, writer, stderr)) => {
+                    streams[1] = Some(writer);
+                    streams[2] = stderr;
+                    previous_stdout = Some(reader);
+                }
+                Err(e) => {
+                    reap(children);
+                    return Err(e);
+                }
+            }
+        }
+        // A stage writing to a file leaves the next one reading an empty pipe.
+        if let Err(e) = stage.apply_redirects(&mut cmd, streams) {
+            reap(children);
+            return Err(e);
+        }
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let words = [std::slice::from_ref(&stage.program), stage.args.as_slice()].concat();
+                let status = ShellCommand::report_spawn_error(&stage.program, &join_for_display(&words), &e);
+                if last {
+                    return Ok((children, Some(status)));
+                }
+                continue;
+            }
         };
+        // Close the shell's copies of the write ends so the next stage sees EOF.
+        drop(cmd);
 
         children.push(child);
     }
 
-    for mut child in children {
-        child.wait()?;
+    Ok((children, None))
+}
+
+/// Kill and wait for the stages already started when a later one can't be,
+/// so they aren't left running or unreaped.
+fn reap(children: Vec<Child>) {
+    for mut started in children {
+        let _ = started.kill();
+        let _ = started.wait();
+    }
+}
+
+/// Execute a pipeline in the foreground and return the exit status of its
+/// last command, or with `pipefail` of the last external command that failed.
+/// Every command is waited for either way. If it is suspended it becomes a
+/// stopped job named `command_line` that `fg` can resume.
+pub fn run_pipeline(
+    commands: &[ParsedCommand],
+    command_line: &str,
+    pipefail: bool,
+    job_manager: &mut JobManager,
+    run_builtin: &mut RunBuiltin,
+) -> io::Result<i32> {
+    let (mut children, builtin_status) = spawn_pipeline(commands, job_manager, run_builtin)?;
+    let Some(pgid) = children.first().map(|leader| leader.id()) else {
+        return Ok(builtin_status.unwrap_or(0));
+    };
+
+    job_manager.set_foreground_pid(Some(pgid));
+    let outcome = jobs::wait_foreground(pgid, &mut children, false, pipefail);
+    job_manager.set_foreground_pid(None);
+
+    if outcome == ForegroundOutcome::Stopped {
+        job_manager.add_stopped_job(pgid, command_line.to_string(), children);
+        return Ok(outcome.status());
     }
 
-    Ok(())
+    Ok(builtin_status.unwrap_or(outcome.status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redirects::RedirectType;
+    use crate::test_util::TempDir;
+    use std::{env, fs};
+
+    #[test]
+    fn test_pipeline_stage_redirects() {
+        let dir = TempDir::new("pipe-redirect");
+        fs::write(dir.join("in.txt"), "foo 1\nbar\nfoo 2\n").unwrap();
+        let d = dir.display();
+
+        let commands = parse_pipeline(&format!("cat < {d}/in.txt | grep foo > {d}/out.txt")).unwrap();
+        let redirected = matches!(commands[0].redirects[..], [RedirectType::StdinFrom(_)])
+            && matches!(commands[1].redirects[..], [RedirectType::StdoutTo(_)]);
+        let status = run_pipeline(&commands, "cat | grep", false, &mut JobManager::new(), &mut |_, _, _| None).unwrap();
+        let out = fs::read_to_string(dir.join("out.txt")).unwrap_or_default();
+
+        assert!(redirected);
+        assert_eq!(status, 0);
+        assert_eq!(out, "foo 1\nfoo 2\n");
+        assert!(parse_pipeline("ls | | wc").is_err());
+    }
+
+    #[test]
+    fn test_quoted_pipes_do_not_split() {
+        let commands = parse_pipeline("echo \"a | b\" | grep 'a|b'").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].args, vec!["a | b"]);
+        assert_eq!(commands[1].args, vec!["a|b"]);
+
+        assert!(is_pipeline("ls | wc"));
+        assert!(!is_pipeline("grep 'a|b' file"));
+        assert!(!is_pipeline("echo $(ls | wc -l)"));
+    }
+
+    #[test]
+    fn test_dup_fd_applies_in_order() {
+        let dir = TempDir::new("dupfd");
+        let d = dir.display();
+        let script = "echo out; echo err >&2";
+
+        ParsedCommand::parse(&format!("sh -c '{script}' > {d}/both 2>&1")).unwrap().execute().unwrap();
+        ParsedCommand::parse(&format!("sh -c '{script}' 2>{d}/err 1>&2")).unwrap().execute().unwrap();
+        let commands = parse_pipeline(&format!("sh -c '{script}' 2>&1 | cat > {d}/piped")).unwrap();
+        run_pipeline(&commands, "sh 2>&1 | cat", false, &mut JobManager::new(), &mut |_, _, _| None).unwrap();
+        let dup = ParsedCommand::parse("ls 2>&1").unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+        let (both, err, piped) = (read("both"), read("err"), read("piped"));
+
+        assert!(matches!(dup.redirects[..], [RedirectType::DupFd { from: 2, to: 1 }]));
+        assert_eq!(both, "out\nerr\n");
+        assert_eq!(err, "out\nerr\n");
+        assert_eq!(piped, "out\nerr\n");
+        assert!(ParsedCommand::parse("ls 2>&x").is_err());
+    }
+
+    #[test]
+    fn test_pipe_stderr_with_stdout() {
+        let dir = TempDir::new("pipe-stderr");
+        let d = dir.display();
+
+        let commands = parse_pipeline(&format!("ls /nonexistent-rshell-dir |& cat > {d}/out")).unwrap();
+        let merged: Vec<bool> = commands.iter().map(|c| c.merge_stderr).collect();
+        run_pipeline(&commands, "ls |& cat", false, &mut JobManager::new(), &mut |_, _, _| None).unwrap();
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+
+        assert_eq!(merged, vec![true, false]);
+        assert!(out.contains("nonexistent-rshell-dir"));
+        assert!(parse_pipeline("ls |&").is_err());
+    }
+
+    #[test]
+    fn test_stage_words_are_expanded() {
+        env::set_var("RSHELL_TEST_STAGE", "a b");
+        let line = "echo $RSHELL_TEST_STAGE \"$RSHELL_TEST_STAGE\" | grep \"${RSHELL_TEST_STAGE}\" ~";
+        let commands = parse_pipeline(line).unwrap();
+
+        assert_eq!(commands[0].args, vec!["a", "b", "a b"]);
+        assert_eq!(commands[1].args, vec!["a b".to_string(), env::var("HOME").unwrap()]);
+    }
+
+    #[test]
+    fn test_stage_substitutions() {
+        let commands = parse_pipeline("echo $(echo sub stitution) `echo x` | cat > \"$(echo out)\"").unwrap();
+        assert_eq!(commands[0].args, vec!["sub", "stitution", "x"]);
+        assert!(matches!(&commands[1].redirects[..], [RedirectType::StdoutTo(file)] if file == "out"));
+    }
+
+    #[test]
+    fn test_stage_globs() {
+        let dir = TempDir::new("pipe-glob");
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("b.txt"), "").unwrap();
+        let d = dir.display();
+
+        let commands = parse_pipeline(&format!("ls {d}/*.txt '{d}/*.txt' | cat")).unwrap();
+        let redirected = ParsedCommand::parse(&format!("echo {d}/?.txt > {d}/out")).unwrap();
+
+        let matches = [format!("{d}/a.txt"), format!("{d}/b.txt")];
+        assert_eq!(commands[0].args, [&matches[..], &[format!("{d}/*.txt")]].concat());
+        assert_eq!(redirected.args, matches);
+    }
 }
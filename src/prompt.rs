@@ -1,5 +1,8 @@
 use std::env;
+use std::fs;
+use std::path::Path;
 use colored::*;
+use crate::command::{color_enabled, Command};
 
 pub struct Prompt;
 
@@ -8,8 +11,102 @@ impl Prompt {
         Self
     }
 
-    pub fn get_string(&self) -> String {
-        let cwd = env::current_dir()
+    /// Render the prompt. `job_count` is the number of active background jobs,
+    /// shown when `RSHELL_PROMPT_JOBS` is set (`always` shows it even when zero).
+    /// Inside a git work tree the branch follows the directory, unless
+    /// `RSHELL_PROMPT_GIT=0`. The `$` is green after a command succeeds and
+    /// red after `last_status` shows it failed. A `PS1` variable replaces the
+    /// default format.
+    pub fn get_string(&self, job_count: usize, last_status: i32) -> String {
+        // `colored` checks NO_COLOR only once; recheck it so a change applies
+        // from the next prompt, to completion listings too.
+        colored::control::set_override(color_enabled());
+
+        if let Ok(ps1) = env::var("PS1") {
+            // Substitutions run on every render so their output stays current.
+            match Command::substitute_commands(&Self::expand_escapes(&ps1)) {
+                Ok(prompt) => return prompt,
+                Err(e) => eprintln!("PS1: {}", e),
+            }
+        }
+
+        let cwd = Self::cwd();
+        let username = Self::username();
+        let hostname = Self::hostname();
+
+        let branch = match env::current_dir() {
+            Ok(dir) if env::var("RSHELL_PROMPT_GIT").map_or(true, |v| v != "0") => Self::git_branch(&dir)
+                .map(|branch| format!(" ({})", branch).magenta().to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let sigil = if Self::is_root() { "#" } else { "$" };
+        let sigil = if last_status == 0 { sigil.green() } else { sigil.red() };
+
+        let jobs = match env::var("RSHELL_PROMPT_JOBS") {
+            Ok(mode) if mode == "always" || (!mode.is_empty() && job_count > 0) => {
+                format!("[{}] ", job_count).yellow().to_string()
+            }
+            _ => String::new(),
+        };
+
+        format!(
+            "{}@{}:{}{} {}{} ",
+            username.green(),
+            hostname.green(),
+            cwd.blue(),
+            branch,
+            jobs,
+            sigil
+        )
+    }
+
+    /// Expand the backslash escapes in `PS1`: `\u` user, `\h` host, `\w` cwd
+    /// with `~`, `\W` its last component, `\$` (`#` for root), `\n` newline,
+    /// `\e` escape for colors like `\e[32m`, and `\\`. `\[` and `\]` are
+    /// dropped, as escape sequences are measured without them. Unknown escapes
+    /// are left as-is.
+    fn expand_escapes(ps1: &str) -> String {
+        let mut result = String::new();
+        let mut chars = ps1.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => result.push_str(&Self::username()),
+                Some('h') => result.push_str(&Self::hostname()),
+                Some('w') => result.push_str(&Self::cwd()),
+                Some('W') => {
+                    let cwd = Self::cwd();
+                    let base = match cwd.rsplit('/').next() {
+                        Some("") | None => &cwd,
+                        Some(base) => base,
+                    };
+                    result.push_str(base);
+                }
+                Some('$') => result.push(if Self::is_root() { '#' } else { '$' }),
+                Some('n') => result.push('\n'),
+                Some('e') => result.push('\x1b'),
+                Some('\\') => result.push('\\'),
+                Some('[' | ']') => {}
+                Some(other) => {
+                    result.push(c);
+                    result.push(other);
+                }
+                None => result.push(c),
+            }
+        }
+
+        result
+    }
+
+    /// The working directory, with `$HOME` shown as `~`.
+    fn cwd() -> String {
+        env::current_dir()
             .map(|p| {
                 let path = p.display().to_string();
                 if let Ok(home) = env::var("HOME") {
@@ -19,23 +116,79 @@ impl Prompt {
                 }
                 path
             })
-            .unwrap_or_else(|_| String::from("?"));
+            .unwrap_or_else(|_| String::from("?"))
+    }
 
-        let username = env::var("USER")
+    fn username() -> String {
+        env::var("USER")
             .or_else(|_| env::var("USERNAME"))
-            .unwrap_or_else(|_| String::from("unknown"));
+            .unwrap_or_else(|_| String::from("unknown"))
+    }
 
-        let hostname = env::var("HOSTNAME")
-            .unwrap_or_else(|_| whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string()));
+    fn hostname() -> String {
+        env::var("HOSTNAME")
+            .unwrap_or_else(|_| whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string()))
+    }
 
-        let prefix = if username == "root" { "# " } else { "$ " };
+    /// The branch checked out in the git work tree containing `dir`, or the
+    /// short commit hash when HEAD is detached. Reads `.git/HEAD` directly
+    /// rather than running `git` on every prompt. A `.git` file, as in
+    /// worktrees and submodules, points to the real git directory.
+    fn git_branch(dir: &Path) -> Option<String> {
+        let dot_git = dir.ancestors().map(|d| d.join(".git")).find(|p| p.exists())?;
+        let git_dir = if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let target = contents.trim().strip_prefix("gitdir:")?.trim();
+            dot_git.parent()?.join(target)
+        } else {
+            dot_git
+        };
 
-        format!(
-            "{}@{}:{} {}",
-            username.green(),
-            hostname.green(),
-            cwd.blue(),
-            prefix.white()
-        )
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let head = head.trim();
+        match head.strip_prefix("ref:") {
+            Some(reference) => {
+                let reference = reference.trim();
+                Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string())
+            }
+            None => Some(head.chars().take(7).collect()),
+        }
+    }
+
+    fn is_root() -> bool {
+        env::var("USER").or_else(|_| env::var("USERNAME")).is_ok_and(|user| user == "root")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_ps1_escapes() {
+        assert_eq!(Prompt::expand_escapes("\\u@\\h"), format!("{}@{}", Prompt::username(), Prompt::hostname()));
+        assert_eq!(Prompt::expand_escapes("\\[\\e[32m\\]>\\n\\\\ \\q"), "\x1b[32m>\n\\ \\q");
+        assert!(Prompt::cwd().ends_with(&Prompt::expand_escapes("\\W")));
+    }
+
+    #[test]
+    fn test_git_branch() {
+        let dir = TempDir::new("git");
+        let nested = dir.join("repo/src/deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("worktree")).unwrap();
+        fs::write(dir.join("repo/.git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+
+        let branch = Prompt::git_branch(&nested);
+        fs::write(dir.join("repo/.git/HEAD"), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+        let detached = Prompt::git_branch(&nested);
+        fs::write(dir.join("worktree/.git"), "gitdir: ../repo/.git\n").unwrap();
+        let worktree = Prompt::git_branch(&dir.join("worktree"));
+
+        assert_eq!(branch.as_deref(), Some("feature/x"));
+        assert_eq!(detached.as_deref(), Some("0123456"));
+        assert_eq!(worktree.as_deref(), Some("0123456"));
     }
 }
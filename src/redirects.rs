@@ -1,6 +1,9 @@
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{self, Write};
 use std::process::{Command, Stdio};
+use std::thread;
+use crate::command::{join_for_display, Command as ShellCommand};
+use crate::jobs;
 
 #[derive(Debug, Clone)]
 pub enum RedirectType {
@@ -10,6 +13,10 @@ pub enum RedirectType {
     StderrTo(String),
     StderrAppend(String),
     BothTo(String),
+    /// `N>&M`: make descriptor `from` a copy of descriptor `to`.
+    DupFd { from: i32, to: i32 },
+    /// `<<< word`: the word, already expanded, plus a newline on stdin.
+    HereString(String),
 }
 
 #[derive(Debug)]
@@ -17,73 +24,95 @@ pub struct ParsedCommand {
     pub program: String,
     pub args: Vec<String>,
     pub redirects: Vec<RedirectType>,
+    /// In a pipeline, send stderr down the pipe along with stdout (`|&`).
+    pub merge_stderr: bool,
 }
 
 impl ParsedCommand {
-    pub fn parse(input: &str) -> Self {
-        let mut tokens = tokenize_with_redirects(input);
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize_with_redirects(input);
         let mut redirects = Vec::new();
         let mut cmd_parts = Vec::new();
 
         let mut i = 0;
         while i < tokens.len() {
-            match tokens[i].as_str() {
+            match tokens[i].0.as_str() {
+                _ if tokens[i].1.is_some() => {
+                    cmd_parts.extend(expand_word(&tokens[i])?);
+                    i += 1;
+                }
+                "<<<" => {
+                    let Some((word, quoted)) = tokens.get(i + 1) else {
+                        return Err("expected a word after '<<<'".to_string());
+                    };
+                    // As in bash, only single quotes keep the word from being expanded.
+                    let word = ShellCommand::parse_args_with_state(word).0.join(" ");
+                    let text = if *quoted == Some('\'') { word } else { ShellCommand::expand_text(&word)? };
+                    redirects.push(RedirectType::HereString(text));
+                    i += 2;
+                }
                 "<" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::StdinFrom(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::StdinFrom(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '<'");
-                        i += 1;
+                        return Err("expected filename after '<'".to_string());
                     }
                 }
-                ">" => {
+                ">" | "1>" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::StdoutTo(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::StdoutTo(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '>'");
-                        i += 1;
+                        return Err("expected filename after '>'".to_string());
                     }
                 }
-                ">>" => {
+                ">>" | "1>>" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::StdoutAppend(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::StdoutAppend(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '>>'");
-                        i += 1;
+                        return Err("expected filename after '>>'".to_string());
                     }
                 }
                 "2>" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::StderrTo(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::StderrTo(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '2>'");
-                        i += 1;
+                        return Err("expected filename after '2>'".to_string());
                     }
                 }
                 "2>>" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::StderrAppend(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::StderrAppend(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '2>>'");
-                        i += 1;
+                        return Err("expected filename after '2>>'".to_string());
                     }
                 }
                 "&>" => {
                     if i + 1 < tokens.len() {
-                        redirects.push(RedirectType::BothTo(tokens[i + 1].clone()));
+                        redirects.push(RedirectType::BothTo(expand_target(&tokens[i + 1])?));
                         i += 2;
                     } else {
-                        eprintln!("Error: expected filename after '&>'");
-                        i += 1;
+                        return Err("expected filename after '&>'".to_string());
                     }
                 }
+                token if token.len() <= 3 && token.starts_with(|c: char| c.is_ascii_digit()) && token.ends_with('>') => {
+                    return Err(format!("{}: only descriptors 1 and 2 can be redirected to a file", token));
+                }
+                token if is_dup_token(token) => {
+                    let (from, to) = token.split_once(">&").unwrap_or_default();
+                    let from = if from.is_empty() { Ok(1) } else { from.parse() };
+                    match (from, to.parse()) {
+                        (Ok(from), Ok(to)) => redirects.push(RedirectType::DupFd { from, to }),
+                        _ => return Err(format!("{}: bad file descriptor", token)),
+                    }
+                    i += 1;
+                }
                 _ => {
-                    cmd_parts.push(tokens[i].clone());
+                    cmd_parts.extend(expand_word(&tokens[i])?);
                     i += 1;
                 }
             }
@@ -96,136 +125,311 @@ impl ParsedCommand {
             Vec::new()
         };
 
-        ParsedCommand {
+        Ok(ParsedCommand {
             program,
             args,
             redirects,
-        }
+            merge_stderr: false,
+        })
     }
 
-    pub fn execute(&self) -> io::Result<()> {
+    /// Run the command with its redirects and return its exit status. A
+    /// command that can't be started is reported here and gets status 127 or
+    /// 126; failing to open a redirect target is returned as an error.
+    pub fn execute(&self) -> io::Result<i32> {
         let mut cmd = Command::new(&self.program);
         cmd.args(&self.args);
+        self.apply_redirects(&mut cmd, Default::default())?;
 
+        let status = match cmd.status() {
+            Ok(status) => status,
+            Err(e) => {
+                let words = [std::slice::from_ref(&self.program), self.args.as_slice()].concat();
+                return Ok(ShellCommand::report_spawn_error(&self.program, &join_for_display(&words), &e));
+            }
+        };
+        if !status.success() {
+            if let Some(code) = status.code() {
+                eprintln!("{}: exited with code {}", self.program, code);
+            }
+        }
+
+        Ok(jobs::exit_code(status))
+    }
+
+    /// Open the redirect targets and attach them to `cmd`. `streams` holds
+    /// what stdin, stdout and stderr are already connected to, such as pipes
+    /// in a pipeline; `None` leaves a stream as the shell's own. Redirects
+    /// apply left to right, so `> out 2>&1` sends both streams to `out`
+    /// while `2>&1 > out` leaves stderr on the terminal.
+    pub fn apply_redirects(&self, cmd: &mut Command, mut streams: [Option<File>; 3]) -> io::Result<()> {
         for redirect in &self.redirects {
             match redirect {
-                RedirectType::StdinFrom(file) => {
-                    let f = File::open(file)?;
-                    cmd.stdin(Stdio::from(f));
-                }
-                RedirectType::StdoutTo(file) => {
-                    let f = File::create(file)?;
-                    cmd.stdout(Stdio::from(f));
-                }
-                RedirectType::StdoutAppend(file) => {
-                    let f = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(file)?;
-                    cmd.stdout(Stdio::from(f));
-                }
-                RedirectType::StderrTo(file) => {
-                    let f = File::create(file)?;
-                    cmd.stderr(Stdio::from(f));
-                }
-                RedirectType::StderrAppend(file) => {
-                    let f = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(file)?;
-                    cmd.stderr(Stdio::from(f));
-                }
+                RedirectType::StdinFrom(file) => streams[0] = Some(File::open(file)?),
+                RedirectType::HereString(text) => streams[0] = Some(text_stream(format!("{}\n", text))?),
+                RedirectType::StdoutTo(file) => streams[1] = Some(File::create(file)?),
+                RedirectType::StdoutAppend(file) => streams[1] = Some(open_append(file)?),
+                RedirectType::StderrTo(file) => streams[2] = Some(File::create(file)?),
+                RedirectType::StderrAppend(file) => streams[2] = Some(open_append(file)?),
                 RedirectType::BothTo(file) => {
                     let f = File::create(file)?;
-                    let f2 = f.try_clone()?;
-                    cmd.stdout(Stdio::from(f));
-                    cmd.stderr(Stdio::from(f2));
+                    streams[2] = Some(f.try_clone()?);
+                    streams[1] = Some(f);
+                }
+                RedirectType::DupFd { from, to } => {
+                    let (Some(from), Some(to)) = (stream_index(*from), stream_index(*to)) else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{}>&{}: only descriptors 0, 1 and 2 can be redirected", from, to),
+                        ));
+                    };
+                    streams[from] = Some(match &streams[to] {
+                        Some(target) => target.try_clone()?,
+                        None => shell_stream(to)?,
+                    });
                 }
             }
         }
 
-        let status = cmd.status()?;
-        if !status.success() {
-            if let Some(code) = status.code() {
-                eprintln!("{}: exited with code {}", self.program, code);
-            }
+        let [stdin, stdout, stderr] = streams;
+        if let Some(f) = stdin {
+            cmd.stdin(Stdio::from(f));
+        }
+        if let Some(f) = stdout {
+            cmd.stdout(Stdio::from(f));
+        }
+        if let Some(f) = stderr {
+            cmd.stderr(Stdio::from(f));
         }
-
         Ok(())
     }
 }
 
-fn tokenize_with_redirects(input: &str) -> Vec<String> {
+/// The words a token stands for once expanded. A quoted token that expands
+/// to nothing is still an empty word, as in `printf '%s\n' ''`.
+fn expand_word((word, quoted): &(String, Option<char>)) -> Result<Vec<String>, String> {
+    let words = ShellCommand::expand_word(word)?;
+    if words.is_empty() && quoted.is_some() {
+        return Ok(vec![String::new()]);
+    }
+    Ok(words)
+}
+
+/// The file name a redirect target expands to, which has to be one word.
+fn expand_target(token: &(String, Option<char>)) -> Result<String, String> {
+    match expand_word(token)?.as_slice() {
+        [target] => Ok(target.clone()),
+        _ => Err(format!("{}: ambiguous redirect", token.0)),
+    }
+}
+
+fn open_append(file: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(file)
+}
+
+fn stream_index(fd: i32) -> Option<usize> {
+    usize::try_from(fd).ok().filter(|&fd| fd <= 2)
+}
+
+/// A copy of the shell's own stdin, stdout or stderr.
+fn shell_stream(index: usize) -> io::Result<File> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        let fd = match index {
+            0 => io::stdin().as_fd().try_clone_to_owned()?,
+            1 => io::stdout().as_fd().try_clone_to_owned()?,
+            _ => io::stderr().as_fd().try_clone_to_owned()?,
+        };
+        Ok(File::from(fd))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = index;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "descriptor duplication not supported on this platform"))
+    }
+}
+
+/// The read end of a pipe that yields `text`, for feeding a string to a
+/// command's stdin. It's written from a thread so a long text can't fill
+/// the pipe and block the shell.
+pub fn text_stream(text: String) -> io::Result<File> {
+    let (reader, mut writer) = pipe()?;
+    thread::spawn(move || writer.write_all(text.as_bytes()));
+    Ok(reader)
+}
+
+/// A pipe as a pair of files, read end first, so either end can be used as
+/// a redirect target.
+pub fn pipe() -> io::Result<(File, File)> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::OwnedFd;
+        let (reader, writer) = io::pipe()?;
+        Ok((File::from(OwnedFd::from(reader)), File::from(OwnedFd::from(writer))))
+    }
+    #[cfg(not(unix))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pipes not supported on this platform"))
+    }
+}
+
+/// Split `input` into words and redirect operators. Words keep their quotes
+/// and backslashes for `ShellCommand::expand_word`, and a `$(...)` or
+/// backtick substitution stays in one word. Each token comes with the first
+/// quote used in it, if any; a quoted token is never an operator.
+fn tokenize_with_redirects(input: &str) -> Vec<(String, Option<char>)> {
     let mut tokens = Vec::new();
     let mut current = String::new();
+    let mut quoted = None;
     let mut in_quotes = false;
+    let mut depth = 0usize;
     let mut quote_char = ' ';
     let mut chars = input.chars().peekable();
 
+    let flush = |tokens: &mut Vec<(String, Option<char>)>, current: &mut String, quoted: &mut Option<char>| {
+        if !current.is_empty() || quoted.is_some() {
+            tokens.push((std::mem::take(current), quoted.take()));
+        }
+    };
+    let operator = |tokens: &mut Vec<(String, Option<char>)>, op: String| tokens.push((op, None));
+
     while let Some(c) = chars.next() {
+        let top_level = !in_quotes && depth == 0;
         match c {
-            '"' | '\'' if !in_quotes => {
+            '\\' if quote_char != '\'' => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            '"' | '\'' | '`' if !in_quotes => {
                 in_quotes = true;
+                if c != '`' {
+                    quoted = quoted.or(Some(c));
+                }
                 quote_char = c;
+                current.push(c);
             }
-            '"' | '\'' if in_quotes && c == quote_char => {
+            '"' | '\'' | '`' if in_quotes && c == quote_char => {
                 in_quotes = false;
+                quote_char = ' ';
+                current.push(c);
             }
-            ' ' if !in_quotes => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
-                }
+            '(' if !in_quotes => {
+                depth += 1;
+                current.push(c);
             }
-            '>' if !in_quotes => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
-                }
+            ')' if !in_quotes => {
+                depth = depth.saturating_sub(1);
+                current.push(c);
+            }
+            ' ' if top_level => flush(&mut tokens, &mut current, &mut quoted),
+            '>' if top_level => {
+                flush(&mut tokens, &mut current, &mut quoted);
                 
                 if chars.peek() == Some(&'>') {
                     chars.next();
-                    tokens.push(">>".to_string());
+                    operator(&mut tokens, ">>".to_string());
+                } else if chars.peek() == Some(&'&') {
+                    chars.next();
+                    operator(&mut tokens, format!(">&{}", take_digits(&mut chars)));
                 } else {
-                    tokens.push(">".to_string());
+                    operator(&mut tokens, ">".to_string());
                 }
             }
-            '<' if !in_quotes => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
+            '<' if top_level => {
+                flush(&mut tokens, &mut current, &mut quoted);
+                let mut lookahead = chars.clone();
+                if lookahead.next() == Some('<') && lookahead.next() == Some('<') {
+                    chars = lookahead;
+                    operator(&mut tokens, "<<<".to_string());
+                } else {
+                    operator(&mut tokens, "<".to_string());
                 }
-                tokens.push("<".to_string());
             }
-            '2' if !in_quotes && chars.peek() == Some(&'>') => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
-                }
+            '0'..='9' if top_level && current.is_empty() && quoted.is_none() && chars.peek() == Some(&'>') => {
                 chars.next();
                 
                 if chars.peek() == Some(&'>') {
                     chars.next();
-                    tokens.push("2>>".to_string());
+                    operator(&mut tokens, format!("{}>>", c));
+                } else if chars.peek() == Some(&'&') {
+                    chars.next();
+                    operator(&mut tokens, format!("{}>&{}", c, take_digits(&mut chars)));
                 } else {
-                    tokens.push("2>".to_string());
+                    operator(&mut tokens, format!("{}>", c));
                 }
             }
-            '&' if !in_quotes && chars.peek() == Some(&'>') => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
-                }
+            '&' if top_level && chars.peek() == Some(&'>') => {
+                flush(&mut tokens, &mut current, &mut quoted);
                 chars.next();
-                tokens.push("&>".to_string());
+                operator(&mut tokens, "&>".to_string());
             }
             _ => current.push(c),
         }
     }
+    flush(&mut tokens, &mut current, &mut quoted);
+
+    tokens
+}
+
+/// Whether `token` is a `N>&M` or `>&M` operator from the tokenizer.
+fn is_dup_token(token: &str) -> bool {
+    token.split_once(">&").is_some_and(|(from, to)| {
+        from.len() <= 1 && from.chars().chain(to.chars()).all(|c| c.is_ascii_digit())
+    })
+}
 
-    if !current.is_empty() {
-        tokens.push(current);
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
     }
+    digits
+}
 
-    tokens
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::{env, fs};
+
+    #[test]
+    fn test_here_string() {
+        let dir = TempDir::new("herestring");
+        let d = dir.display();
+        env::set_var("RSHELL_TEST_HERE_STRING", "a b");
+
+        let run = |line: &str| ParsedCommand::parse(line).unwrap().execute().unwrap();
+        run(&format!("wc -c <<< \"hello\" > {d}/count"));
+        run(&format!("cat <<< $RSHELL_TEST_HERE_STRING > {d}/expanded"));
+        run(&format!("cat <<< \"x $RSHELL_TEST_HERE_STRING\" > {d}/double"));
+        run(&format!("cat <<< '$RSHELL_TEST_HERE_STRING' > {d}/literal"));
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+        let (count, expanded, literal) = (read("count"), read("expanded"), read("literal"));
+        let double = read("double");
+
+        assert_eq!(count.trim(), "6");
+        assert_eq!(expanded, "a b\n");
+        assert_eq!(double, "x a b\n");
+        assert_eq!(literal, "$RSHELL_TEST_HERE_STRING\n");
+        assert!(ParsedCommand::parse("cat <<<").is_err());
+    }
+
+    #[test]
+    fn test_redirected_words_are_expanded() {
+        let dir = TempDir::new("redirect-expand");
+        let d = dir.display();
+        env::set_var("RSHELL_TEST_REDIRECT", "out put");
+
+        let run = |line: &str| ParsedCommand::parse(line).unwrap().execute().unwrap();
+        run(&format!("printf '%s|' $RSHELL_TEST_REDIRECT '$RSHELL_TEST_REDIRECT' '' > \"{d}/$RSHELL_TEST_REDIRECT\""));
+        let ambiguous = ParsedCommand::parse(&format!("echo > {d}/$RSHELL_TEST_REDIRECT"));
+
+        let out = fs::read_to_string(dir.join("out put")).unwrap_or_default();
+
+        assert_eq!(out, "out|put|$RSHELL_TEST_REDIRECT||");
+        assert!(ambiguous.is_err());
+    }
 }
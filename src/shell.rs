@@ -1,18 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::env;
-use crate::command::Command;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::builtins;
+use crate::command::{expand_tilde, find_in_path, is_executable, join_for_display, quote_for_display, Command};
 use crate::prompt::Prompt;
 use crate::history::History;
-use crate::editor::LineEditor;
-use crate::jobs::JobManager;
-use crate::pipes::{parse_pipeline, run_pipeline};
+use crate::editor::{EditMode, LineEditor};
+use crate::jobs::{self, ForegroundOutcome, JobManager, JobStatus};
+use crate::pipes::{is_pipeline, parse_pipeline, run_pipeline, spawn_pipeline};
 use crate::redirects::ParsedCommand;
 use crate::heredoc;
+use crate::signal_handler::{self, SignalHandler};
+use crate::suggest;
+use crate::variables;
+
+/// Options listed by `set -o`.
+const OPTIONS: &[&str] = &["debugparse", "emacs", "expand_aliases", "huponexit", "pipefail", "vi"];
+
+/// Startup file, re-run by `reload` and Ctrl-X Ctrl-R.
+const RC_FILE: &str = "~/.rshellrc";
+
+/// Aliases and variables saved on exit and restored at the next start.
+const STATE_FILE: &str = "~/.rshell_state";
+
+/// A command that couldn't run: the message to report and the exit status
+/// to record. Plain `String` errors are failures with status 1.
+struct Failure {
+    status: i32,
+    message: String,
+}
+
+impl Failure {
+    /// A syntax error or misused builtin, status 2.
+    fn usage(message: String) -> Self {
+        Failure { status: 2, message }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure { status: 1, message }
+    }
+}
 
 pub struct Shell {
     prompt: Prompt,
     history: History,
     editor: LineEditor,
     job_manager: JobManager,
+    prev_dir: Option<PathBuf>,
+    /// Command run after each successful `cd`, set with the `chpwd` builtin.
+    chpwd_hook: Option<String>,
+    /// Lines read at the prompt so far, for `$LINENO`.
+    lines_read: usize,
+    /// Skip the startup banner (`-q`).
+    quiet: bool,
+    /// Don't run the startup file (`--norc`).
+    norc: bool,
+    /// Don't restore or save the state file (`--no-state`).
+    no_state: bool,
+    /// `RC_FILE` and `STATE_FILE` with `~` expanded.
+    rc_file: String,
+    state_file: String,
+    /// Variables set with `export` or `NAME=value`, to save in the state file.
+    set_variables: BTreeSet<String>,
+    /// Variables the startup file sets. They are left to it rather than
+    /// saved, so `export PATH=$PATH:~/bin` there doesn't grow each session.
+    rc_variables: BTreeSet<String>,
+    /// Aliases the startup file defines, likewise left to it.
+    rc_aliases: BTreeSet<String>,
+    /// Print timings of startup steps (`--debug` or `RSHELL_DEBUG`).
+    debug: bool,
+    /// A pipeline fails if any of its commands fails (`set -o pipefail`).
+    pipefail: bool,
+    /// Send SIGHUP to the jobs still running when the shell exits
+    /// (`set -o huponexit`), instead of leaving them to run on.
+    huponexit: bool,
+    /// Print each parsed line to stderr (`set -o debugparse`), on at
+    /// startup when `RSHELL_DEBUG_PARSE=1`.
+    debug_parse: bool,
+    /// Defined with `alias`, sorted so they list in order.
+    aliases: BTreeMap<String, String>,
+    /// Expand aliases; on at the prompt and off in scripts, like bash.
+    expand_aliases: bool,
+    /// Reading commands at the prompt rather than from a script or `-c`.
+    interactive: bool,
+    /// The unread lines of the script or sourced file being run, where a
+    /// heredoc in it finds its body. `None` at the prompt, where the body is
+    /// read from the terminal.
+    script_lines: Option<VecDeque<String>>,
     running: bool,
 }
 
@@ -21,131 +101,265 @@ impl Shell {
         if let Ok(exe_path) = env::current_exe() {
             env::set_var("SHELL", exe_path.to_string_lossy().to_string());
         }
+        variables::start_clock();
 
         Self {
             prompt: Prompt::new(),
             history: History::new(),
             editor: LineEditor::new(),
             job_manager: JobManager::new(),
+            prev_dir: None,
+            chpwd_hook: None,
+            lines_read: 0,
+            quiet: false,
+            norc: false,
+            no_state: false,
+            rc_file: expand_tilde(RC_FILE),
+            state_file: expand_tilde(STATE_FILE),
+            set_variables: BTreeSet::new(),
+            rc_variables: BTreeSet::new(),
+            rc_aliases: BTreeSet::new(),
+            debug: false,
+            pipefail: false,
+            huponexit: false,
+            debug_parse: env::var("RSHELL_DEBUG_PARSE").is_ok_and(|v| v == "1"),
+            aliases: BTreeMap::new(),
+            expand_aliases: true,
+            interactive: true,
+            script_lines: None,
             running: true,
         }
     }
 
-    fn read_input_with_continuation(&mut self) -> Result<String, std::io::Error> {
+    /// Read a command at the prompt, asking for more lines while it is
+    /// unfinished. Returns `None` at end of input.
+    fn read_input_with_continuation(&mut self) -> io::Result<Option<String>> {
         let mut full_input = String::new();
         let mut first_line = true;
 
         loop {
             let prompt = if first_line {
-                self.prompt.get_string()
+                let start = Instant::now();
+                let prompt = self.prompt.get_string(self.job_manager.active_count(), variables::last_status());
+                if self.lines_read == 0 {
+                    self.debug_timing("First prompt", start);
+                }
+                prompt
             } else {
                 "> ".to_string()
             };
 
-            let line = self.editor.read_line(&prompt, &mut self.history)?;
-
-            let line_trimmed = line.trim_end();
-            let has_trailing_backslash = line_trimmed.ends_with('\\') && {
-                let chars: Vec<char> = line_trimmed.chars().collect();
-                let mut count = 0;
-                for c in chars.iter().rev() {
-                    if *c == '\\' { count += 1; } else { break; }
-                }
-                count % 2 == 1
+            self.editor.set_aliases(self.aliases.keys().cloned().collect());
+            let Some(line) = self.editor.read_line(&prompt, &mut self.history)? else {
+                return Ok(None);
             };
-
-            if has_trailing_backslash {
-                let without_backslash = &line_trimmed[..line_trimmed.len() - 1];
-                if !first_line && !full_input.is_empty() {
-                    full_input.push(' ');
-                }
-                full_input.push_str(without_backslash);
-                first_line = false;
-                continue;
-            }
-
-            if !first_line {
-                full_input.push('\n');
-            }
             full_input.push_str(&line);
             first_line = false;
 
+            // Keep the newline; a backslash before it is removed when the lines are joined.
             if Command::needs_line_continuation(&full_input) {
+                full_input.push('\n');
                 continue;
             }
 
             break;
         }
 
-        Ok(full_input)
+        Ok(Some(full_input))
     }
 
-    pub fn run(&mut self) {
-        println!("Type 'help' for available commands\n");
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn set_norc(&mut self, norc: bool) {
+        self.norc = norc;
+    }
+
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    /// With `--debug`, print how long `step` has taken since `start`.
+    fn debug_timing(&self, step: &str, start: Instant) {
+        if self.debug {
+            eprintln!("DEBUG: {} took {:?}", step, start.elapsed());
+        }
+    }
+
+    pub fn set_no_state(&mut self, no_state: bool) {
+        self.no_state = no_state;
+    }
+
+    /// Restore the aliases and variables saved by the last session, unless
+    /// `--no-state` was given. Runs after `~/.rshellrc`, and skips what that
+    /// defines, so definitions there win.
+    fn load_state(&mut self) {
+        if self.no_state || !Path::new(&self.state_file).exists() {
+            return;
+        }
+        let contents = match fs::read_to_string(&self.state_file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("rshell: can't read {}: {}", self.state_file, e);
+                return;
+            }
+        };
+        // Blank out the skipped lines so errors keep their line numbers.
+        let contents: Vec<&str> = contents
+            .lines()
+            .map(|line| if self.defined_by_rc(line) { "" } else { line })
+            .collect();
+        self.run_lines(&self.state_file.clone(), &contents.join("\n"));
+    }
+
+    /// Whether `line` of the state file redefines an alias or variable that
+    /// the startup file defined.
+    fn defined_by_rc(&self, line: &str) -> bool {
+        let definition = line.split_once(' ').and_then(|(command, rest)| Some((command, rest.split_once('=')?.0)));
+        match definition {
+            Some(("alias", name)) => self.rc_aliases.contains(name),
+            Some(("export", name)) => self.rc_variables.contains(name),
+            _ => false,
+        }
+    }
 
-        #[cfg(unix)]
-        unsafe {
-            use libc::{signal, SIGINT, SIG_IGN};
-            signal(SIGINT, SIG_IGN);
+    /// Write the aliases and the variables set in this shell, other than the
+    /// startup file's, to the state file as commands that recreate them. The
+    /// file is written under a temporary name and renamed, so a failed write
+    /// keeps the old one.
+    fn save_state(&self) {
+        if self.no_state {
+            return;
         }
+        let mut contents = Vec::new();
+        let _ = writeln!(contents, "# Saved by rshell on exit; read at startup unless --no-state is given.");
+        let aliases: BTreeMap<String, String> = self
+            .aliases
+            .iter()
+            .filter(|(name, _)| !self.rc_aliases.contains(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let _ = list_aliases(&aliases, &mut contents);
+        for name in self.set_variables.difference(&self.rc_variables) {
+            if let Ok(value) = env::var(name) {
+                let _ = writeln!(contents, "export {}={}", name, quote_for_display(&value));
+            }
+        }
+
+        let temp = format!("{}.tmp", self.state_file);
+        if let Err(e) = fs::write(&temp, contents).and_then(|()| fs::rename(&temp, &self.state_file)) {
+            eprintln!("rshell: can't save {}: {}", self.state_file, e);
+            let _ = fs::remove_file(&temp);
+        }
+    }
+
+    /// Run `~/.rshellrc` unless `--norc` was given. A missing file is skipped
+    /// silently; errors in it are reported like `source` reports them.
+    fn load_rc(&mut self) {
+        if self.norc || !Path::new(&self.rc_file).exists() {
+            return;
+        }
+        if let Err(e) = self.source_rc() {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Run the startup file, noting the variables it sets in `rc_variables`
+    /// instead of `set_variables`, and the aliases it defines in `rc_aliases`.
+    fn source_rc(&mut self) -> Result<(), String> {
+        let outer = std::mem::take(&mut self.set_variables);
+        let aliases_before = self.aliases.clone();
+        let result = self.source_file(&self.rc_file.clone());
+        let rc_variables = std::mem::replace(&mut self.set_variables, outer);
+        self.rc_variables.extend(rc_variables);
+        let rc_aliases = self.aliases.iter().filter(|(name, value)| aliases_before.get(*name) != Some(*value));
+        self.rc_aliases.extend(rc_aliases.map(|(name, _)| name.clone()));
+        result
+    }
+
+    /// Print `~/.rshell_motd` if it exists, or the default banner. Nothing is
+    /// printed with `-q` or when input isn't a terminal.
+    fn print_banner(&self) {
+        if self.quiet || !io::stdin().is_terminal() {
+            return;
+        }
+        match fs::read_to_string(expand_tilde("~/.rshell_motd")) {
+            Ok(motd) => print!("{}", motd),
+            Err(_) => println!("Type 'help' for available commands\n"),
+        }
+    }
+
+    pub fn run(&mut self) {
+        self.print_banner();
+
+        SignalHandler::new().setup_handler();
+        signal_handler::ignore_job_control_signals();
+        let start = Instant::now();
+        self.load_rc();
+        self.load_state();
+        self.debug_timing("Startup files", start);
+
+        self.interact(Self::read_input_with_continuation);
+    }
 
+    /// Run the commands `read` returns until it reaches end of input or
+    /// `exit` runs, then save the state and deal with the jobs left running.
+    /// Every way out of the prompt ends here.
+    fn interact(&mut self, mut read: impl FnMut(&mut Self) -> io::Result<Option<String>>) {
         while self.running {
             self.job_manager.update_jobs();
+            self.print_job_notifications();
 
-            match self.read_input_with_continuation() {
-                Ok(input) => {
-                    let mut trimmed = input.trim().to_string();
-                    if trimmed.is_empty() {
+            match read(self) {
+                Ok(None) => break,
+                Ok(Some(input)) => {
+                    // Show the command a `!!` or `!N` stood for before running it, like bash.
+                    let input = match self.history.expand(&input) {
+                        Ok(expanded) if expanded != input => {
+                            println!("{}", expanded);
+                            expanded
+                        }
+                        Ok(_) => input,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            continue;
+                        }
+                    };
+                    self.lines_read += input.lines().count().max(1);
+                    variables::set_line_number(self.lines_read);
+                    if Self::prepare_line(&input).is_none() {
                         continue;
                     }
+                    self.history.add(&Command::join_line_continuations(&input));
 
-                    let background = trimmed.ends_with('&');
-                    if background {
-                        trimmed = trimmed[..trimmed.len() - 1].trim().to_string();
-                    }
-
-                    self.history.add(trimmed.clone());
-
-                    if trimmed.contains("<<") {
-                        if let Some((command, delimiter, quoted)) = heredoc::parse_heredoc(&trimmed) {
-                            if let Err(e) = heredoc::execute_heredoc(&command, &delimiter, quoted) {
-                                eprintln!("Error: {}", e);
-                            }
+                    // Lines pasted together arrive as one input and run one after another,
+                    // and a heredoc among them takes its body from the lines after it.
+                    let outer_lines = self.script_lines.replace(input.lines().map(str::to_string).collect());
+                    let mut pending = String::new();
+                    while let Some(line) = self.script_lines.as_mut().and_then(VecDeque::pop_front) {
+                        if !pending.is_empty() {
+                            pending.push('\n');
                         }
-                    } else if (trimmed.contains('<') || trimmed.contains('>')) && !trimmed.contains('|') {
-                        let parsed = ParsedCommand::parse(&trimmed);
-                        if let Err(e) = parsed.execute() {
-                            eprintln!("Error: {}", e);
+                        pending.push_str(&line);
+                        if Command::needs_line_continuation(&pending) {
+                            continue;
                         }
-                    } else if trimmed.contains('|') {
-                        let commands = parse_pipeline(&trimmed);
-
-                        if background {
-                            let commands_clone = commands.clone();
-                            std::thread::spawn(move || {
-                                if let Err(e) = run_pipeline(commands_clone) {
-                                    eprintln!("Pipeline error: {}", e);
-                                }
-                            });
-                        } else {
-                            if let Err(e) = run_pipeline(commands) {
-                                eprintln!("Pipeline error: {}", e);
-                            }
+                        let Some((trimmed, background)) = Self::prepare_line(&std::mem::take(&mut pending)) else {
+                            continue;
+                        };
+                        let start = Instant::now();
+                        if let Err(e) = self.execute_line(&trimmed, background) {
+                            eprintln!("{}", e);
                         }
-                    } else {
-                        if let Some(cmd) = Command::parse(&trimmed) {
-                            match cmd {
-                                Command::History => self.history.list(),
-                                Command::Jobs => self.list_jobs(),
-                                Command::Fg(job_id) => self.foreground_job(job_id),
-                                Command::Bg(job_id) => self.background_job(job_id),
-                                Command::Exit => self.running = false,
-                                _ => {
-                                    self.running = cmd.execute(&mut self.job_manager);
-                                }
-                            }
+                        if !background {
+                            Self::report_time(&trimmed, start.elapsed());
+                        }
+                        if !self.running {
+                            break;
                         }
                     }
+                    self.script_lines = outer_lines;
                 }
                 Err(e) => {
                     eprintln!("Error reading input: {}", e);
@@ -153,49 +367,1114 @@ impl Shell {
                 }
             }
         }
+
+        self.save_state();
+        self.job_manager.shutdown(self.huponexit);
+        self.report_surviving_jobs();
+    }
+
+    /// Print what happened to background jobs since the last prompt.
+    fn print_job_notifications(&mut self) {
+        for notification in self.job_manager.take_completed_notifications() {
+            println!("{}", notification);
+        }
     }
 
-    fn list_jobs(&self) {
-        let jobs = self.job_manager.list_jobs();
-        if jobs.is_empty() {
-            println!("No background jobs");
-        } else {
-            for job in jobs {
-                let status = match job.status {
-                    crate::jobs::JobStatus::Running => "Running",
-                    crate::jobs::JobStatus::Stopped => "Stopped",
-                    crate::jobs::JobStatus::Done => "Done",
-                };
-                println!("[{}] {} {} {}", job.id, status, job.pid, job.command);
+    /// On exit, list the background jobs that are still running, so their
+    /// pids can be found again later.
+    fn report_surviving_jobs(&mut self) {
+        self.job_manager.update_jobs();
+        self.print_job_notifications();
+        let jobs = self.job_manager.surviving_jobs();
+        let disowned = self.job_manager.disowned_jobs();
+        if jobs.is_empty() && disowned.is_empty() {
+            return;
+        }
+        println!("Still running after exit:");
+        for job in jobs {
+            println!("  [{}] {} {}", job.id, job.pid, job.command);
+        }
+        for job in disowned {
+            println!("  {} {} (disowned)", job.pid, job.command);
+        }
+    }
+
+    /// Join continued lines, trim, and split off a trailing `&`. Returns `None`
+    /// for a blank line.
+    fn prepare_line(input: &str) -> Option<(String, bool)> {
+        let mut trimmed = Command::join_line_continuations(input).trim().to_string();
+        if trimmed.is_empty() {
+            return None;
+        }
+
+        let background = trimmed.ends_with('&') && !trimmed.ends_with("&&");
+        if background {
+            trimmed = trimmed[..trimmed.len() - 1].trim().to_string();
+        }
+        Some((trimmed, background))
+    }
+
+    /// Run one line. Commands joined by `&&` run only after a success and those
+    /// joined by `||` only after a failure; only the last one can be in the
+    /// background. Parse errors and failures to start a command are returned
+    /// for the caller to report and end the line; errors from a running
+    /// command are printed as they happen.
+    fn execute_line(&mut self, trimmed: &str, background: bool) -> Result<(), String> {
+        let parts = Command::split_unquoted(trimmed, &["&&", "||"]);
+        if let Some(i) = parts.iter().position(|(_, part)| part.trim().is_empty()) {
+            let token = parts[i].0.or_else(|| parts.get(i + 1).and_then(|(next, _)| *next));
+            variables::set_last_status(2);
+            return Err(format!("syntax error near unexpected token `{}'", token.unwrap_or("&&")));
+        }
+
+        let last = parts.len() - 1;
+        for (i, (connector, part)) in parts.iter().enumerate() {
+            let succeeded = variables::last_status() == 0;
+            match connector {
+                Some("&&") if !succeeded => continue,
+                Some("||") if succeeded => continue,
+                _ => {}
+            }
+            let command = self.expand_aliases(part.trim());
+            if let Err(failure) = self.execute_command(&command, background && i == last) {
+                variables::set_last_status(failure.status);
+                return Err(failure.message);
+            }
+            if !self.running {
+                break;
             }
         }
+        Ok(())
     }
 
-    fn foreground_job(&mut self, job_id: u32) {
-        if let Some(mut job) = self.job_manager.remove_job(job_id) {
-            println!("{}", job.command);
-            if let Some(ref mut child) = job.process {
-                match child.wait() {
-                    Ok(status) => {
-                        println!("[{}] Done (exit: {})", job.id, status);
+    /// Run a single command or pipeline and record its exit status in `$?`:
+    /// 0 for success, 1 when a builtin fails and 2 when it is misused.
+    fn execute_command(&mut self, trimmed: &str, background: bool) -> Result<(), Failure> {
+        if let Some(Command::Time(command)) = Command::parse_time(trimmed) {
+            return self.time_command(&command, background);
+        }
+        if trimmed.contains("<<") && !trimmed.contains("<<<") {
+            if let Some(heredoc) = heredoc::parse_heredoc(trimmed) {
+                self.print_parsed(&heredoc);
+                let status = match &mut self.script_lines {
+                    // A heredoc on the last line at the prompt reads its body from the terminal.
+                    Some(lines) if self.interactive && lines.is_empty() => heredoc::execute_heredoc(&heredoc),
+                    Some(lines) => {
+                        let mut body = iter::from_fn(|| lines.pop_front().map(Ok));
+                        heredoc::run_heredoc(&heredoc, &mut body, false)
+                    }
+                    None => heredoc::execute_heredoc(&heredoc),
+                }
+                .map_err(|e| format!("Error: {}", e))?;
+                variables::set_last_status(status);
+            }
+        } else if (trimmed.contains('<') || trimmed.contains('>')) && !is_pipeline(trimmed) {
+            let parsed = ParsedCommand::parse(trimmed).map_err(|e| Failure::usage(format!("Error: {}", e)))?;
+            self.print_parsed(&parsed);
+            let status = parsed.execute().map_err(|e| format!("Error: {}", e))?;
+            variables::set_last_status(status);
+        } else if is_pipeline(trimmed) {
+            let commands = parse_pipeline(trimmed).map_err(Failure::usage)?;
+            self.print_parsed(&commands);
+
+            let (history, aliases) = (&self.history, &self.aliases);
+            let run_builtin = &mut |stage: &ParsedCommand, out: &mut dyn Write, job_manager: &mut JobManager| {
+                Self::pipeline_builtin(stage, out, job_manager, history, aliases)
+            };
+            if background {
+                let (children, _) = spawn_pipeline(&commands, &mut self.job_manager, run_builtin)
+                    .map_err(|e| format!("Pipeline error: {}", e))?;
+                if let Some(pgid) = children.first().map(|leader| leader.id()) {
+                    self.job_manager.add_job(pgid, trimmed.to_string(), children);
+                }
+                variables::set_last_status(0);
+            } else {
+                let status = run_pipeline(&commands, trimmed, self.pipefail, &mut self.job_manager, run_builtin)
+                    .map_err(|e| format!("Pipeline error: {}", e))?;
+                variables::set_last_status(status);
+            }
+        } else {
+            if let Some(mut cmd) = Command::parse(trimmed).map_err(Failure::usage)? {
+                // The caller strips the trailing '&', so carry it over here.
+                if let Command::External { background: ref mut bg, .. } = cmd {
+                    *bg = background;
+                }
+                self.print_parsed(&cmd);
+                // `exit` leaves the status of the previous command for the shell to exit with.
+                if !matches!(cmd, Command::Exit) {
+                    variables::set_last_status(0);
+                }
+                match cmd {
+                    Command::Cd(path) => self.change_directory(path.as_deref()),
+                    Command::History => self.history.list(&mut io::stdout()).map_err(|e| e.to_string())?,
+                    Command::Jobs { long, pids } => {
+                        list_jobs(&self.job_manager, long, pids, &mut io::stdout()).map_err(|e| e.to_string())?
                     }
-                    Err(e) => {
-                        eprintln!("Error waiting for job {}: {}", job.id, e);
+                    Command::Fg(spec) => self.foreground_job(spec.as_deref()),
+                    Command::Bg(spec) => self.background_job(spec.as_deref()),
+                    Command::Kill { target, signal } => self.kill(&target, signal.as_deref())?,
+                    Command::Chpwd(args) => self.set_chpwd_hook(&args),
+                    Command::Source(path) => self.source_file(&path)?,
+                    Command::Reload => self.source_rc()?,
+                    Command::Set(args) => self.set_option(&args).map_err(Failure::usage)?,
+                    Command::Alias(args) => self.alias(&args)?,
+                    Command::Unalias(names) => self.unalias(&names)?,
+                    Command::Type(names) => {
+                        let status = describe_commands(&names, &self.aliases, &mut io::stdout())
+                            .map_err(|e| e.to_string())?;
+                        variables::set_last_status(status);
+                    }
+                    Command::Exit => self.running = false,
+                    _ => {
+                        if let Command::Assign(vars) = &cmd {
+                            self.set_variables.extend(vars.iter().map(|(name, _)| name.clone()));
+                        } else if let Command::Export(vars) = &cmd {
+                            self.set_variables.extend(vars.iter().map(|(name, _)| name.clone()));
+                        }
+                        let status = cmd.execute(&mut self.job_manager);
+                        variables::set_last_status(status);
+                        if let Command::External { program, .. } = &cmd {
+                            if status == 127 && self.interactive {
+                                self.suggest_command(program);
+                            }
+                        }
                     }
                 }
+            }
+        }
+        Ok(())
+    }
+
+    /// `time COMMAND`: run COMMAND, which may be a pipeline or a builtin,
+    /// then print the elapsed real time and the user and system CPU time of
+    /// the shell and the children it waited for to stderr, like bash. `$?` is
+    /// left as COMMAND's status.
+    fn time_command(&mut self, command: &str, background: bool) -> Result<(), Failure> {
+        let start = Instant::now();
+        let (user, sys) = cpu_times();
+        let command = self.expand_aliases(command);
+        let result = if command.is_empty() {
+            variables::set_last_status(0);
+            Ok(())
+        } else {
+            self.execute_command(&command, background)
+        };
+        let (end_user, end_sys) = cpu_times();
+        eprintln!(
+            "\nreal\t{}\nuser\t{}\nsys\t{}",
+            format_timing(start.elapsed()),
+            format_timing(end_user.saturating_sub(user)),
+            format_timing(end_sys.saturating_sub(sys))
+        );
+        result
+    }
+
+    /// After `program` wasn't found, print the PATH commands, builtins and
+    /// aliases with a similar name, if any.
+    fn suggest_command(&mut self, program: &str) {
+        if program.contains('/') || find_in_path(program).is_some() {
+            return;
+        }
+        let builtin_names = builtins::BUILTINS.iter().map(|b| b.name);
+        let alias_names = self.aliases.keys().map(String::as_str);
+        let path_names = self.editor.path_commands().iter().map(String::as_str);
+        let names: Vec<String> = suggest::similar(program, path_names.chain(builtin_names).chain(alias_names))
+            .iter()
+            .map(|name| format!("'{}'", name))
+            .collect();
+        match names.as_slice() {
+            [] => {}
+            [name] => eprintln!("Did you mean {}?", name),
+            _ => eprintln!("Did you mean one of {}?", names.join(", ")),
+        }
+    }
+
+    /// Run a pipeline stage that names a builtin in the shell, writing its
+    /// output to `out`. Builtins that change the shell's state, like `cd`,
+    /// `fg` or `export NAME=value`, are refused: other shells run them in a
+    /// subshell where the change is lost. Returns `None` for stages that must
+    /// be started as external commands instead: non-builtins, `env` with a
+    /// command, stages with redirects or `|&`, and builtin names that don't
+    /// parse as the builtin, like `cat` with no file reading the pipe.
+    fn pipeline_builtin(
+        stage: &ParsedCommand,
+        out: &mut dyn Write,
+        job_manager: &mut JobManager,
+        history: &History,
+        aliases: &BTreeMap<String, String>,
+    ) -> Option<i32> {
+        if builtins::find(&stage.program).is_none() || !stage.redirects.is_empty() || stage.merge_stderr {
+            return None;
+        }
+        let words = [std::slice::from_ref(&stage.program), stage.args.as_slice()].concat();
+        let cmd = Command::parse(&join_for_display(&words)).ok()??;
+
+        let result = match cmd {
+            Command::Env { ref command, .. } if !command.is_empty() => return None,
+            // Other shells only time whole pipelines, so this is the `time` program.
+            Command::Time(_) => return None,
+            Command::History => history.list(out),
+            Command::Jobs { long, pids } => list_jobs(job_manager, long, pids, out),
+            Command::Alias(ref args) if args.is_empty() => list_aliases(aliases, out),
+            Command::Type(ref names) => return Some(describe_commands(names, aliases, out).unwrap_or(1)),
+            Command::Export(ref vars) if vars.is_empty() => return Some(cmd.execute_to(out, false, job_manager)),
+            Command::Cd(_)
+            | Command::Fg(_)
+            | Command::Bg(_)
+            | Command::Disown(_)
+            | Command::Kill { .. }
+            | Command::Chpwd(_)
+            | Command::Source(_)
+            | Command::Reload
+            | Command::Set(_)
+            | Command::Alias(_)
+            | Command::Unalias(_)
+            | Command::Assign(_)
+            | Command::Export(_)
+            | Command::Exit => {
+                eprintln!("{}: can't be used in a pipeline", stage.program);
+                return Some(1);
+            }
+            _ => return Some(cmd.execute_to(out, false, job_manager)),
+        };
+        Some(match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}: {}", stage.program, e);
+                1
+            }
+        })
+    }
+
+    /// Replace the first word of each pipeline stage in `command` with its
+    /// alias, as long as the result starts with another alias. Each alias is
+    /// used once, so `alias ls='ls -F'` runs the `ls` command instead of
+    /// looping. Quoted or escaped words are never aliases. Does nothing while
+    /// `expand_aliases` is off.
+    fn expand_aliases(&self, command: &str) -> String {
+        if !self.expand_aliases || self.aliases.is_empty() {
+            return command.to_string();
+        }
+        let mut expanded = String::new();
+        for (separator, stage) in Command::split_unquoted(command, &["|&", "|"]) {
+            expanded.push_str(separator.unwrap_or(""));
+            let body = stage.trim_start();
+            expanded.push_str(&stage[..stage.len() - body.len()]);
+
+            let mut body = body.to_string();
+            let mut used = HashSet::new();
+            loop {
+                let word = body.split(char::is_whitespace).next().unwrap_or("");
+                let Some(value) = self.aliases.get(word) else { break };
+                if !used.insert(word.to_string()) {
+                    break;
+                }
+                body = format!("{}{}", value, &body[word.len()..]);
+            }
+            expanded.push_str(&body);
+        }
+        expanded
+    }
+
+    /// `alias` lists the aliases, `alias NAME` prints one and
+    /// `alias NAME=value` defines one. Unknown names are reported and make the
+    /// status 1, but the other arguments still take effect.
+    fn alias(&mut self, args: &[(String, Option<String>)]) -> Result<(), String> {
+        if args.is_empty() {
+            return list_aliases(&self.aliases, &mut io::stdout()).map_err(|e| e.to_string());
+        }
+        for (name, value) in args {
+            match value {
+                Some(value) => {
+                    self.aliases.insert(name.clone(), value.clone());
+                }
+                None => match self.aliases.get(name) {
+                    Some(value) => println!("alias {}={}", name, quote_alias(value)),
+                    None => {
+                        eprintln!("alias: {}: not found", name);
+                        variables::set_last_status(1);
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove each alias in `names`, or all of them with `-a`.
+    fn unalias(&mut self, names: &[String]) -> Result<(), String> {
+        if names.first().is_some_and(|name| name == "-a") {
+            self.aliases.clear();
+            return Ok(());
+        }
+        for name in names {
+            if self.aliases.remove(name).is_none() {
+                eprintln!("unalias: {}: not found", name);
+                variables::set_last_status(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run each line of the file at `path`. A bad line is reported as
+    /// `path: line N: message` and the remaining lines still run.
+    fn source_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("source: {}: {}", path, e))?;
+        self.run_lines(path, &contents);
+        Ok(())
+    }
+
+    /// Run the lines of `source` as a script without reading from the
+    /// terminal, for `rshell FILE` and `rshell -c COMMAND`. Errors are
+    /// reported as `name: line N: message`. Aliases aren't expanded unless
+    /// the script turns on `set -o expand_aliases`. Returns the status of the
+    /// last command.
+    pub fn run_script(&mut self, name: &str, source: &str) -> i32 {
+        SignalHandler::new().setup_handler();
+        signal_handler::ignore_job_control_signals();
+        self.expand_aliases = false;
+        self.interactive = false;
+
+        self.run_lines(name, source);
+        variables::last_status()
+    }
+
+    /// Run `contents` line by line, joining continued lines and skipping
+    /// comments. `$LINENO` counts lines from the start of `contents` and is
+    /// restored afterwards.
+    fn run_lines(&mut self, name: &str, contents: &str) {
+        let outer_line = variables::line_number();
+        let lines: VecDeque<String> = contents.lines().map(str::to_string).collect();
+        let total = lines.len();
+        let outer_lines = self.script_lines.replace(lines);
+        let mut pending = String::new();
+        let mut start_line = 0;
+        while let Some(line) = self.script_lines.as_mut().and_then(VecDeque::pop_front) {
+            // Heredocs take lines too, so count from what is left.
+            let line_number = total - self.script_lines.as_ref().map_or(0, VecDeque::len);
+            if pending.is_empty() {
+                start_line = line_number;
             } else {
+                pending.push('\n');
+            }
+            pending.push_str(&line);
+            if Command::needs_line_continuation(&pending) {
+                continue;
+            }
+
+            let input = std::mem::take(&mut pending);
+            if input.trim_start().starts_with('#') {
+                continue;
+            }
+            if let Some((line, background)) = Self::prepare_line(&input) {
+                variables::set_line_number(start_line);
+                if let Err(e) = self.execute_line(&line, background) {
+                    eprintln!("{}: line {}: {}", name, start_line, e);
+                }
+            }
+            if !self.running {
+                break;
+            }
+        }
+
+        if !pending.is_empty() {
+            eprintln!("{}: line {}: unexpected end of file while looking for closing quote", name, start_line);
+            variables::set_last_status(2);
+        }
+        self.script_lines = outer_lines;
+        variables::set_line_number(outer_line);
+    }
+
+    /// `set -o` lists the options, `set -o NAME` turns one on and `set +o NAME`
+    /// turns it off. `emacs` and `vi` pick the editing mode, so turning one off
+    /// turns the other on.
+    fn set_option(&mut self, args: &[String]) -> Result<(), String> {
+        match args {
+            [] => {
+                for name in OPTIONS {
+                    println!("{:<15}\t{}", name, if self.option(name) { "on" } else { "off" });
+                }
+            }
+            [flag] if flag == "-o" || flag == "+o" => return self.set_option(&[]),
+            [flag, name] if flag == "-o" || flag == "+o" => {
+                let on = flag == "-o";
+                match name.as_str() {
+                    "emacs" => self.editor.set_mode(if on { EditMode::Emacs } else { EditMode::Vi }),
+                    "vi" => self.editor.set_mode(if on { EditMode::Vi } else { EditMode::Emacs }),
+                    "debugparse" => self.debug_parse = on,
+                    "pipefail" => self.pipefail = on,
+                    "expand_aliases" => self.expand_aliases = on,
+                    "huponexit" => self.huponexit = on,
+                    _ => return Err(format!("set: {}: invalid option name", name)),
+                }
+            }
+            _ => return Err("set: usage: set [-o|+o] [option]".to_string()),
+        }
+        Ok(())
+    }
+
+    fn option(&self, name: &str) -> bool {
+        match name {
+            "emacs" => self.editor.mode() == EditMode::Emacs,
+            "vi" => self.editor.mode() == EditMode::Vi,
+            "debugparse" => self.debug_parse,
+            "pipefail" => self.pipefail,
+            "expand_aliases" => self.expand_aliases,
+            "huponexit" => self.huponexit,
+            _ => false,
+        }
+    }
+
+    /// Dump the parsed form of a line to stderr when `debugparse` is on.
+    fn print_parsed(&self, parsed: &impl std::fmt::Debug) {
+        if self.debug_parse {
+            eprintln!("{:#?}", parsed);
+        }
+    }
+
+    /// Print how long a foreground command took when it ran for at least
+    /// `REPORTTIME` seconds, like zsh. Does nothing when `REPORTTIME` is unset.
+    fn report_time(command: &str, elapsed: Duration) {
+        let threshold = match env::var("REPORTTIME").ok().and_then(|v| v.parse::<f64>().ok()) {
+            Some(threshold) if threshold >= 0.0 => threshold,
+            _ => return,
+        };
+        if elapsed.as_secs_f64() >= threshold {
+            eprintln!("{}  {:.3}s total", command, elapsed.as_secs_f64());
+        }
+    }
+
+    fn change_directory(&mut self, path: Option<&str>) {
+        let target = match path {
+            Some("-") => match &self.prev_dir {
+                Some(dir) => dir.clone(),
+                None => {
+                    eprintln!("cd: OLDPWD not set");
+                    variables::set_last_status(1);
+                    return;
+                }
+            },
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var("HOME").unwrap_or_else(|_| "/".to_string())),
+        };
+
+        let current = env::current_dir().ok();
+        if let Err(e) = env::set_current_dir(&target) {
+            eprintln!("cd: {}", e);
+            variables::set_last_status(1);
+            return;
+        }
+
+        if path == Some("-") {
+            println!("{}", target.display());
+        }
+        self.prev_dir = current;
+
+        // Take the hook while it runs so a `cd` inside it doesn't recurse.
+        if let Some(hook) = self.chpwd_hook.take() {
+            if let Err(e) = self.execute_line(&hook, false) {
+                eprintln!("chpwd: {}", e);
+            }
+            if self.chpwd_hook.is_none() {
+                self.chpwd_hook = Some(hook);
+            }
+        }
+    }
+
+    fn set_chpwd_hook(&mut self, args: &[String]) {
+        match args {
+            [] => {
+                if let Some(hook) = &self.chpwd_hook {
+                    println!("{}", hook);
+                }
+            }
+            [flag] if flag == "-r" => self.chpwd_hook = None,
+            _ => self.chpwd_hook = Some(args.join(" ")),
+        }
+    }
+
+    /// Job id for a job spec: `%N` or a bare `N`, `%+` or `%%` for the current
+    /// (newest) job, `%-` for the one before it, or `%string` for the job whose
+    /// command starts with `string`.
+    fn resolve_job_spec(&self, spec: &str) -> Option<u32> {
+        let jobs = self.job_manager.list_jobs();
+        let spec = spec.strip_prefix('%').unwrap_or(spec);
+        match spec {
+            "+" | "%" | "" => jobs.last().map(|job| job.id),
+            "-" => jobs.iter().rev().nth(1).map(|job| job.id),
+            _ => match spec.parse::<u32>() {
+                Ok(id) => jobs.iter().find(|job| job.id == id).map(|job| job.id),
+                Err(_) => jobs.iter().rev().find(|job| job.command.starts_with(spec)).map(|job| job.id),
+            },
+        }
+    }
+
+    /// Resolve an optional job spec for `builtin`, defaulting to the current job.
+    fn job_for(&self, builtin: &str, spec: Option<&str>) -> Option<u32> {
+        let id = self.resolve_job_spec(spec.unwrap_or("%+"));
+        if id.is_none() {
+            variables::set_last_status(1);
+            match spec {
+                Some(spec) => eprintln!("{}: job {} not found", builtin, spec),
+                None => eprintln!("{}: no current job", builtin),
+            }
+        }
+        id
+    }
+
+    fn foreground_job(&mut self, spec: Option<&str>) {
+        let Some(job_id) = self.job_for("fg", spec) else {
+            return;
+        };
+        if let Some(mut job) = self.job_manager.remove_job(job_id) {
+            println!("{}", job.command);
+            if job.processes.is_empty() {
                 println!("[{}] Job already completed", job.id);
+                return;
+            }
+            // A job already running in the background only needs the terminal.
+            let stopped = job.status == JobStatus::Stopped;
+            job.status = JobStatus::Running;
+            self.job_manager.set_foreground_pid(Some(job.pgid));
+            let outcome = jobs::wait_foreground(job.pgid, &mut job.processes, stopped, self.pipefail);
+            self.job_manager.set_foreground_pid(None);
+            variables::set_last_status(outcome.status());
+            match outcome {
+                ForegroundOutcome::Exited(code) => {
+                    println!("[{}] Done (exit: {})", job.id, code);
+                }
+                ForegroundOutcome::Killed(signal) => {
+                    println!("[{}] Terminated (signal {})", job.id, signal);
+                }
+                ForegroundOutcome::Stopped => self.job_manager.restore_stopped_job(job),
+            }
+        }
+    }
+
+    fn background_job(&mut self, spec: Option<&str>) {
+        let Some(job_id) = self.job_for("bg", spec) else {
+            return;
+        };
+        let error = match self.job_manager.get_job_mut(job_id) {
+            Some(job) if job.status == JobStatus::Stopped => match jobs::resume(job.pgid) {
+                Ok(()) => {
+                    job.status = JobStatus::Running;
+                    println!("[{}] {} &", job_id, job.command);
+                    return;
+                }
+                Err(e) => e,
+            },
+            Some(_) => "job already running".to_string(),
+            None => format!("job %{} not found", job_id),
+        };
+        eprintln!("bg: {}", error);
+        variables::set_last_status(1);
+    }
+
+    /// `kill [-signal] target`. A target starting with `%` is a job spec and the
+    /// whole job is signalled; anything else is a pid.
+    fn kill(&mut self, target: &str, signal: Option<&str>) -> Result<(), String> {
+        let signal = signal.unwrap_or("TERM");
+        let result = if target.starts_with('%') {
+            match self.resolve_job_spec(target).and_then(|id| self.job_manager.get_job(id)) {
+                // Jobs run in their own process group; signal all of it.
+                Some(job) => signal_handler::send_signal_to_pid(-(job.pgid as i32), signal).map(|()| {
+                    // A stopped job can't act on the signal until it runs again.
+                    if job.status == JobStatus::Stopped {
+                        let _ = jobs::resume(job.pgid);
+                    }
+                }),
+                None => Err(format!("job {} not found", target)),
+            }
+        } else {
+            match target.parse() {
+                Ok(pid) => signal_handler::send_signal_to_pid(pid, signal),
+                Err(_) => Err(format!("{}: arguments must be process or job IDs", target)),
             }
+        };
+        result.map_err(|e| format!("kill: {}", e))
+    }
+}
+
+/// Write the job table as `[id] status pid command`. `long` adds the
+/// process group after the pid; `pids` writes only the pids, one per line,
+/// for `kill $(jobs -p)`.
+fn list_jobs(job_manager: &JobManager, long: bool, pids: bool, out: &mut dyn Write) -> io::Result<()> {
+    let jobs = job_manager.list_jobs();
+    if pids {
+        for job in jobs {
+            writeln!(out, "{}", job.pid)?;
+        }
+        return Ok(());
+    }
+    if jobs.is_empty() {
+        writeln!(out, "No background jobs")?;
+    }
+    for job in jobs {
+        let status = match job.status {
+            JobStatus::Running => "Running",
+            JobStatus::Stopped => "Stopped",
+            JobStatus::Done => "Done",
+        };
+        if long {
+            writeln!(out, "[{}] {} {} pgid {} {}", job.id, status, job.pid, job.pgid, job.command)?;
         } else {
-            eprintln!("fg: job {} not found", job_id);
+            writeln!(out, "[{}] {} {} {}", job.id, status, job.pid, job.command)?;
         }
     }
+    Ok(())
+}
+
+/// Write every alias as `alias name='value'`, which can be run to define it again.
+fn list_aliases(aliases: &BTreeMap<String, String>, out: &mut dyn Write) -> io::Result<()> {
+    for (name, value) in aliases {
+        writeln!(out, "alias {}={}", name, quote_alias(value))?;
+    }
+    Ok(())
+}
 
-    fn background_job(&mut self, job_id: u32) {
-        if self.job_manager.get_job(job_id).is_some() {
-            println!("[{}] continued in background", job_id);
+/// Write what each of `names` would run, like `type`: an alias, a builtin or
+/// the path of a program. Names that are none of these are reported on
+/// stderr and make the status 1.
+fn describe_commands(names: &[String], aliases: &BTreeMap<String, String>, out: &mut dyn Write) -> io::Result<i32> {
+    let mut status = 0;
+    for name in names {
+        if let Some(value) = aliases.get(name) {
+            writeln!(out, "{} is aliased to `{}'", name, value)?;
+        } else if builtins::find(name).is_some() {
+            writeln!(out, "{} is a shell builtin", name)?;
+        } else if name.contains('/') && is_executable(Path::new(name)) {
+            writeln!(out, "{} is {}", name, name)?;
+        } else if let Some(path) = find_in_path(name).filter(|_| !name.contains('/')) {
+            writeln!(out, "{} is {}", name, path.display())?;
         } else {
-            eprintln!("bg: job {} not found", job_id);
+            eprintln!("type: {}: not found", name);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// User and system CPU time used so far by the shell and its waited-for
+/// children.
+fn cpu_times() -> (Duration, Duration) {
+    let mut user = Duration::ZERO;
+    let mut sys = Duration::ZERO;
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } == 0 {
+            user += timeval_duration(usage.ru_utime);
+            sys += timeval_duration(usage.ru_stime);
         }
     }
+    (user, sys)
+}
+
+fn timeval_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec.max(0) as u64, (time.tv_usec.max(0) as u32) * 1000)
+}
+
+/// Format `duration` the way bash's `time` does, as `1m2.345s`.
+fn format_timing(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}m{}.{:03}s", secs / 60, secs % 60, duration.subsec_millis())
+}
+
+/// Single-quote `value` for reuse as shell input.
+fn quote_alias(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::sync::Mutex;
+
+    /// `$?` is global, so tests that check it take turns.
+    static LAST_STATUS_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_resolve_job_spec() {
+        let mut shell = Shell::new();
+        assert_eq!(shell.resolve_job_spec("%+"), None);
+        shell.job_manager.add_job(100, "sleep 100".to_string(), Vec::new());
+        shell.job_manager.add_job(200, "vim notes".to_string(), Vec::new());
+
+        assert_eq!(shell.resolve_job_spec("%1"), Some(1));
+        assert_eq!(shell.resolve_job_spec("2"), Some(2));
+        assert_eq!(shell.resolve_job_spec("%3"), None);
+        assert_eq!(shell.resolve_job_spec("%+"), Some(2));
+        assert_eq!(shell.resolve_job_spec("%%"), Some(2));
+        assert_eq!(shell.resolve_job_spec("%-"), Some(1));
+        assert_eq!(shell.resolve_job_spec("%sle"), Some(1));
+        assert_eq!(shell.resolve_job_spec("%emacs"), None);
+    }
+
+    #[test]
+    fn test_and_or_status() {
+        let dir = TempDir::new("andor-status");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+
+        let lines = [
+            format!("printf 'b\\na\\n' > {d}/out && touch {d}/ok"),
+            format!("sh -c 'exit 3' > {d}/none || touch {d}/fallback"),
+            format!("false && touch {d}/skipped || touch {d}/recovered"),
+        ];
+        for line in &lines {
+            shell.execute_line(line, false).unwrap();
+        }
+        let status = variables::last_status();
+        shell.execute_line(&format!("true && sh -c 'exit 3' > {d}/none"), false).unwrap();
+        let failed = variables::last_status();
+        shell.execute_line("true || rshell-no-such-command", false).unwrap();
+        let skipped = variables::last_status();
+        shell.execute_line("false || rshell-no-such-command", false).unwrap();
+        let missing = variables::last_status();
+        let empty = shell.execute_line("true && && true", false);
+
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+        let exists = |name: &str| dir.join(name).exists();
+        let flags = (exists("ok"), exists("fallback"), exists("skipped"), exists("recovered"));
+
+        assert_eq!(out, "b\na\n");
+        assert_eq!(flags, (true, true, false, true));
+        assert_eq!((status, failed, skipped, missing), (0, 3, 0, 127));
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn test_and_or_with_redirected_pipelines() {
+        let dir = TempDir::new("andor");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+
+        let lines = [
+            format!("printf 'b\\na\\n' | sort > {d}/out && touch {d}/ok"),
+            format!("true | false > {d}/none || touch {d}/fallback"),
+            format!("false | true && touch {d}/lastwins"),
+            format!("false && touch {d}/skipped || touch {d}/recovered"),
+        ];
+        for line in &lines {
+            shell.execute_line(line, false).unwrap();
+        }
+        let status = variables::last_status();
+        shell.execute_line("false | false", false).unwrap();
+        let failed = variables::last_status();
+
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+        let exists = |name: &str| dir.join(name).exists();
+        let flags = (exists("ok"), exists("fallback"), exists("lastwins"), exists("skipped"), exists("recovered"));
+
+        assert_eq!(out, "a\nb\n");
+        assert_eq!(flags, (true, true, true, false, true));
+        assert_eq!((status, failed), (0, 1));
+    }
+
+    #[test]
+    fn test_pipefail() {
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+        let statuses: Vec<i32> = ["false | true", "set -o pipefail", "false | true", "sh -c 'exit 3' | false | true", "true | true"]
+            .iter()
+            .map(|line| {
+                shell.execute_line(line, false).unwrap();
+                variables::last_status()
+            })
+            .collect();
+        shell.execute_line("true | rshell-no-such-command", false).unwrap();
+        let missing = variables::last_status();
+
+        assert_eq!(statuses, vec![0, 0, 1, 1, 0]);
+        assert_eq!(missing, 127);
+        assert!(shell.option("pipefail"));
+    }
+
+    #[test]
+    fn test_debugparse_option() {
+        let mut shell = Shell::new();
+        shell.execute_line("set -o debugparse", false).unwrap();
+        assert!(shell.option("debugparse"));
+        // The option belongs to this shell, not to the commands it runs.
+        assert!(env::var_os("RSHELL_DEBUG_PARSE").is_none());
+        shell.execute_line("set +o debugparse", false).unwrap();
+        assert!(!shell.option("debugparse"));
+    }
+
+    #[test]
+    fn test_builtin_exit_statuses() {
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+        let mut status_of = |line: &str| {
+            let _ = shell.execute_line(line, false);
+            variables::last_status()
+        };
+
+        assert_eq!(status_of("cd /nonexistent-rshell-dir"), 1);
+        assert_eq!(status_of("pwd"), 0);
+        assert_eq!(status_of("cat /nonexistent-rshell-file"), 1);
+        assert_eq!(status_of("cat"), 2);
+        assert_eq!(status_of("set -o nosuchoption"), 2);
+        assert_eq!(status_of("fg"), 1);
+        assert_eq!(status_of("echo ok"), 0);
+    }
+
+    #[test]
+    fn test_builtins_in_pipelines() {
+        let dir = TempDir::new("pipe-builtin");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+
+        shell.execute_line(&format!("echo b a | tr ' ' '\\n' | sort > {d}/out"), false).unwrap();
+        shell.execute_line(&format!("help -s | grep history > {d}/help"), false).unwrap();
+        let cwd = env::current_dir().unwrap();
+        shell.execute_line("echo x | cd /", false).unwrap();
+        let cd_status = variables::last_status();
+        let cwd_after = env::current_dir().unwrap();
+
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+        let help = fs::read_to_string(dir.join("help")).unwrap_or_default();
+
+        assert_eq!(out, "a\nb\n");
+        assert!(help.starts_with("history "));
+        assert_eq!((cd_status, cwd_after), (1, cwd));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let dir = TempDir::new("alias");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+
+        shell.execute_line("alias greet='echo hi' loud=\"greet there\" echo='echo say'", false).unwrap();
+        let expanded = [shell.expand_aliases("loud you | greet"), shell.expand_aliases("'greet' x")];
+        shell.execute_line(&format!("alias | grep loud > {d}/list"), false).unwrap();
+        shell.execute_line("alias nosuch", false).unwrap();
+        let missing_status = variables::last_status();
+        shell.execute_line("unalias greet", false).unwrap();
+        let after_unalias = shell.expand_aliases("greet");
+        shell.execute_line("unalias -a", false).unwrap();
+
+        let list = fs::read_to_string(dir.join("list")).unwrap_or_default();
+
+        assert_eq!(expanded, ["echo say hi there you | echo say hi", "'greet' x"]);
+        assert_eq!(list, "alias loud='greet there'\n");
+        assert_eq!(missing_status, 1);
+        assert_eq!(after_unalias, "greet");
+        assert!(shell.aliases.is_empty());
+    }
+
+    #[test]
+    fn test_run_script() {
+        let dir = TempDir::new("script");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+
+        let script = format!("# comment\necho one \\\n  two > {d}/out\ncat {d}/missing\nexit\necho never >> {d}/out\n");
+        let status = Shell::new().run_script("test.sh", &script);
+        let unterminated = Shell::new().run_script("test.sh", "echo 'open");
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+
+        assert_eq!(out, "one two\n");
+        assert_eq!(status, 1);
+        assert_eq!(unterminated, 2);
+    }
+
+    #[test]
+    fn test_script_heredoc_reads_script_lines() {
+        let dir = TempDir::new("script-heredoc");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+
+        let script = format!("cat <<EOF > {d}/out\nhello from body\nEOF\necho $LINENO > {d}/line\n");
+        let status = Shell::new().run_script("test.sh", &script);
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+        let line = fs::read_to_string(dir.join("line")).unwrap_or_default();
+
+        assert_eq!(status, 0);
+        assert_eq!(out, "hello from body\n");
+        assert_eq!(line, "4\n");
+    }
+
+    #[test]
+    fn test_script_alias_expansion() {
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let define = "alias rshell_hi='echo hi'";
+
+        let off = Shell::new().run_script("rshell: -c", &format!("{define}\nrshell_hi"));
+        let on = Shell::new().run_script("rshell: -c", &format!("set -o expand_aliases\n{define}\nrshell_hi"));
+        let mut interactive = Shell::new();
+        interactive.execute_line(define, false).unwrap();
+        let expanded = interactive.expand_aliases("rshell_hi there");
+
+        assert_eq!((off, on), (127, 0));
+        assert_eq!(expanded, "echo hi there");
+    }
+
+    #[test]
+    fn test_dash_c_pipelines_and_redirects() {
+        let dir = TempDir::new("dash-c");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+
+        let piped = Shell::new().run_script("rshell: -c", &format!("echo hi | tr a-z A-Z > {d}/out"));
+        let redirected = Shell::new().run_script("rshell: -c", &format!("sort < {d}/out 2>&1 | cat >> {d}/out"));
+        let failed = Shell::new().run_script("rshell: -c", "true | false");
+        let raw = crossterm::terminal::is_raw_mode_enabled().unwrap_or(false);
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+
+        assert_eq!((piped, redirected, failed), (0, 0, 1));
+        assert_eq!(out, "HI\nHI\n");
+        assert!(!raw);
+    }
+
+    #[test]
+    fn test_describe_commands() {
+        let aliases = BTreeMap::from([("ll".to_string(), "ls -l".to_string())]);
+        let names: Vec<String> = ["ll", "cd", "sh"].iter().map(|s| s.to_string()).collect();
+        let mut out = Vec::new();
+        let status = describe_commands(&names, &aliases, &mut out).unwrap();
+        let missing = describe_commands(&["no-such-rshell-cmd".to_string()], &aliases, &mut Vec::new()).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(status, 0);
+        assert_eq!(lines[..2], ["ll is aliased to `ls -l'", "cd is a shell builtin"]);
+        assert!(lines[2].starts_with("sh is /") && lines[2].ends_with("/sh"));
+        assert_eq!(missing, 1);
+    }
+
+    #[test]
+    fn test_time() {
+        assert!(matches!(Command::parse_time("time ls | wc -l"), Some(Command::Time(c)) if c == "ls | wc -l"));
+        assert!(matches!(Command::parse_time("time"), Some(Command::Time(c)) if c.is_empty()));
+        assert!(Command::parse_time("timeout 1 ls").is_none());
+        assert!(Command::parse_time("time=1").is_none());
+        assert_eq!(format_timing(Duration::from_millis(61_234)), "1m1.234s");
+
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        assert_eq!(Shell::new().run_script("test.sh", "time true | false"), 1);
+        assert_eq!(Shell::new().run_script("test.sh", "time echo hi > /dev/null"), 0);
+    }
+
+    #[test]
+    fn test_list_jobs_flags() {
+        let mut manager = JobManager::new();
+        let mut sleep = std::process::Command::new("sleep");
+        sleep.arg("5");
+        jobs::set_process_group(&mut sleep, None);
+        let child = sleep.spawn().unwrap();
+        let pid = child.id();
+        manager.add_job(pid, "sleep 5".to_string(), vec![child]);
+
+        let list = |long, pids| {
+            let mut out = Vec::new();
+            list_jobs(&manager, long, pids, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let (plain, long, pids) = (list(false, false), list(true, false), list(false, true));
+        for child in &mut manager.get_job_mut(1).unwrap().processes {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+
+        assert_eq!(plain, format!("[1] Running {pid} sleep 5\n"));
+        assert_eq!(long, format!("[1] Running {pid} pgid {pid} sleep 5\n"));
+        assert_eq!(pids, format!("{pid}\n"));
+        assert!(matches!(Command::parse("jobs -lp"), Ok(Some(Command::Jobs { long: true, pids: true }))));
+        assert!(Command::parse("jobs -x").is_err());
+    }
+
+    #[test]
+    fn test_end_of_input_saves_state_and_hangs_up() {
+        let dir = TempDir::new("eof");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+        shell.state_file = format!("{d}/state");
+
+        let after = format!("touch {d}/after");
+        let lines = [Some("set -o huponexit"), Some("sh -c 'sleep 5' &"), Some("alias ll='ls -l'"), None, Some(&after)];
+        let mut lines = lines.into_iter().map(|line| line.map(str::to_string));
+        shell.interact(|_| Ok(lines.next().flatten()));
+
+        let state = fs::read_to_string(dir.join("state")).unwrap_or_default();
+        let ran_after = dir.join("after").exists();
+
+        assert!(state.contains("alias ll='ls -l'\n"));
+        assert!(!ran_after);
+        assert!(shell.job_manager.surviving_jobs().is_empty());
+    }
+
+    #[test]
+    fn test_pasted_heredoc_takes_following_lines() {
+        let dir = TempDir::new("paste-heredoc");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let mut shell = Shell::new();
+        shell.state_file = format!("{d}/state");
+
+        let mut inputs = [format!("cat <<EOF > {d}/out\nbody\nEOF\ntouch {d}/after")].into_iter();
+        shell.interact(|_| Ok(inputs.next()));
+
+        let out = fs::read_to_string(dir.join("out")).unwrap_or_default();
+        assert_eq!(out, "body\n");
+        assert!(dir.join("after").exists());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = TempDir::new("state");
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        let state_file = format!("{}/state", dir.display());
+
+        let mut shell = Shell::new();
+        shell.state_file = state_file.clone();
+        shell.execute_line("export RSHELL_TEST_SAVED='a b'", false).unwrap();
+        shell.execute_line("alias greet='echo hi'", false).unwrap();
+        shell.save_state();
+        env::remove_var("RSHELL_TEST_SAVED");
+
+        let mut restored = Shell::new();
+        restored.state_file = state_file;
+        restored.load_state();
+        let value = env::var("RSHELL_TEST_SAVED");
+
+        assert_eq!(value.as_deref(), Ok("a b"));
+        assert_eq!(restored.aliases.get("greet").map(String::as_str), Some("echo hi"));
+        assert!(restored.set_variables.contains("RSHELL_TEST_SAVED"));
+    }
+
+    #[test]
+    fn test_state_leaves_rc_definitions_to_rc() {
+        let dir = TempDir::new("state-rc");
+        let d = dir.display();
+        let _guard = LAST_STATUS_LOCK.lock().unwrap();
+        env::set_var("RSHELL_TEST_RC_PATH", "/usr/bin");
+        let rc = "export RSHELL_TEST_RC_PATH=$RSHELL_TEST_RC_PATH:/opt/bin\nalias ll='ls -l'\n";
+        let stale = "export RSHELL_TEST_RC_PATH=/usr/bin:/opt/bin:/opt/bin\nalias ll='ls -la'\n";
+        fs::write(dir.join("rc"), rc).unwrap();
+        fs::write(dir.join("state"), format!("{stale}export RSHELL_TEST_KEPT=1\n")).unwrap();
+
+        let mut shell = Shell::new();
+        shell.rc_file = format!("{d}/rc");
+        shell.state_file = format!("{d}/state");
+        shell.load_rc();
+        shell.load_state();
+        let path = env::var("RSHELL_TEST_RC_PATH");
+        shell.execute_line("alias la='ls -a'", false).unwrap();
+        shell.save_state();
+        let saved = fs::read_to_string(dir.join("state")).unwrap_or_default();
+
+        assert_eq!(path.as_deref(), Ok("/usr/bin:/opt/bin"));
+        assert_eq!(shell.aliases.get("ll").map(String::as_str), Some("ls -l"));
+        assert!(saved.contains("export RSHELL_TEST_KEPT=1\n"));
+        assert!(!saved.contains("RSHELL_TEST_RC_PATH"));
+        assert!(saved.contains("alias la='ls -a'\n"));
+        assert!(!saved.contains("alias ll="));
+    }
 }
@@ -1,72 +1,187 @@
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
 
-pub struct SignalHandler {
-    current_child: Arc<Mutex<Option<u32>>>,
-}
+static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
+/// Process group of the foreground job, or 0 while the shell is at the prompt.
+static FOREGROUND_PGID: AtomicI32 = AtomicI32::new(0);
+
+/// Forwards Ctrl+C to the foreground child. The child lives in a static
+/// because that's all a signal handler can reach, so every `SignalHandler`
+/// shares it.
+pub struct SignalHandler;
 
 impl SignalHandler {
     pub fn new() -> Self {
-        SignalHandler {
-            current_child: Arc::new(Mutex::new(None)),
-        }
+        SignalHandler
     }
 
+    /// Register the foreground child's process group, or `None` when the
+    /// shell is back at the prompt.
     pub fn set_child(&self, pid: Option<u32>) {
-        if let Ok(mut child) = self.current_child.lock() {
-            *child = pid;
-        }
+        FOREGROUND_PGID.store(pid.map_or(0, |pid| pid as i32), Ordering::SeqCst);
     }
 
     pub fn get_child(&self) -> Option<u32> {
-        if let Ok(child) = self.current_child.lock() {
-            *child
-        } else {
-            None
+        match FOREGROUND_PGID.load(Ordering::SeqCst) {
+            0 => None,
+            pgid => Some(pgid as u32),
         }
     }
 
+    /// Catch SIGINT instead of ignoring it. With a child registered the signal
+    /// is passed on to its process group; otherwise it does nothing. Being
+    /// caught rather than ignored, SIGINT is back to the default in children.
     pub fn setup_handler(&self) {
-        let current_child = Arc::clone(&self.current_child);
-        
         #[cfg(unix)]
         {
-            use std::sync::atomic::{AtomicBool, Ordering};
-            static HANDLER_SET: AtomicBool = AtomicBool::new(false);
-            
-            if HANDLER_SET.swap(true, Ordering::SeqCst) {
-                return;
-            }
+            use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+            let action = SigAction::new(SigHandler::Handler(forward_sigint), SaFlags::SA_RESTART, SigSet::empty());
+            let _ = unsafe { sigaction(Signal::SIGINT, &action) };
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn forward_sigint(_: libc::c_int) {
+    forward_sigint_to(FOREGROUND_PGID.load(Ordering::SeqCst));
+}
 
-            std::thread::spawn(move || {
-                use nix::sys::signal::{self, Signal};
-                use nix::unistd::Pid;
-                
-                loop {
-                    std::thread::sleep(std::time::Duration::from_millis(100));
-                    
-                    if let Ok(child_lock) = current_child.lock() {
-                        if let Some(pid) = *child_lock {
-                            let _ = signal::kill(Pid::from_raw(pid as i32), Signal::SIGINT);
-                        }
-                    }
-                }
-            });
+/// Send SIGINT to process group `pgid`, if there is one; 0 means the shell
+/// is at the prompt. Only async-signal-safe calls, as it runs in a handler.
+#[cfg(unix)]
+fn forward_sigint_to(pgid: i32) {
+    if pgid > 0 {
+        unsafe {
+            libc::kill(-pgid, libc::SIGINT);
         }
     }
 }
 
-pub fn send_sigint_to_pid(pid: u32) -> Result<(), String> {
+/// Send `signal`, a name like `TERM` or `SIGTERM` or a number, to `pid`.
+/// A negative pid signals that whole process group.
+pub fn send_signal_to_pid(pid: i32, signal: &str) -> Result<(), String> {
     #[cfg(unix)]
     {
         use nix::sys::signal::{self, Signal};
         use nix::unistd::Pid;
-        
-        signal::kill(Pid::from_raw(pid as i32), Signal::SIGINT)
+        use std::str::FromStr;
+
+        let parsed = match signal.parse::<i32>() {
+            Ok(number) => Signal::try_from(number).ok(),
+            Err(_) => {
+                let name = signal.to_ascii_uppercase();
+                let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+                Signal::from_str(&name).ok()
+            }
+        };
+        let signal = parsed.ok_or_else(|| format!("{}: invalid signal specification", signal))?;
+
+        signal::kill(Pid::from_raw(pid), signal)
             .map_err(|e| format!("Failed to send signal: {}", e))
     }
-    
+
     #[cfg(not(unix))]
     {
+        let _ = (pid, signal);
         Err("Signal handling not supported on this platform".to_string())
     }
 }
+
+/// Keep the shell running while it hands the terminal to jobs: Ctrl-Z should
+/// stop the foreground job, not the shell, and taking the terminal back must
+/// not stop it either. Children get the defaults back, see
+/// `jobs::set_process_group`.
+pub fn ignore_job_control_signals() {
+    #[cfg(unix)]
+    unsafe {
+        use libc::{signal, SIGTSTP, SIGTTIN, SIGTTOU, SIG_IGN};
+        signal(SIGTSTP, SIG_IGN);
+        signal(SIGTTIN, SIG_IGN);
+        signal(SIGTTOU, SIG_IGN);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn record_sigint(_: libc::c_int) {
+    SIGINT_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Sleep for `duration`, returning early if SIGINT arrives. A duration too
+/// long to reach sleeps until then. Returns `false` when the sleep was
+/// interrupted.
+pub fn interruptible_sleep(duration: Duration) -> bool {
+    let deadline = Instant::now().checked_add(duration);
+    SIGINT_RECEIVED.store(false, Ordering::SeqCst);
+
+    // The shell's handler forwards SIGINT to the foreground job, and there is
+    // none during the builtin sleep, so record it instead until the sleep ends.
+    #[cfg(unix)]
+    let previous = {
+        use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+        let action = SigAction::new(SigHandler::Handler(record_sigint), SaFlags::empty(), SigSet::empty());
+        unsafe { sigaction(Signal::SIGINT, &action) }.ok()
+    };
+
+    let mut completed = true;
+    loop {
+        if SIGINT_RECEIVED.swap(false, Ordering::SeqCst) {
+            completed = false;
+            break;
+        }
+        let remaining = deadline.map_or(Duration::MAX, |deadline| deadline.saturating_duration_since(Instant::now()));
+        if remaining.is_zero() {
+            break;
+        }
+        std::thread::sleep(remaining.min(Duration::from_millis(50)));
+    }
+
+    #[cfg(unix)]
+    if let Some(previous) = previous {
+        use nix::sys::signal::{sigaction, Signal};
+        let _ = unsafe { sigaction(Signal::SIGINT, &previous) };
+    }
+
+    completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Calls the handler's body rather than raising SIGINT, which would reach
+    // the whole test process while other tests run children.
+    #[cfg(unix)]
+    #[test]
+    fn test_forward_sigint_to_process_group() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut sleep = std::process::Command::new("sleep");
+        sleep.arg("5");
+        crate::jobs::set_process_group(&mut sleep, None);
+        let mut child = sleep.spawn().unwrap();
+
+        forward_sigint_to(0);
+        let running = child.try_wait().unwrap().is_none();
+        forward_sigint_to(child.id() as i32);
+
+        assert!(running);
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGINT));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_signal_to_pid_parses_signals() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::Command;
+
+        for (signal, number) in [("term", 15), ("SIGKILL", 9), ("9", 9)] {
+            let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+            assert_eq!(send_signal_to_pid(child.id() as i32, signal), Ok(()));
+            assert_eq!(child.wait().unwrap().signal(), Some(number));
+        }
+        assert_eq!(
+            send_signal_to_pid(std::process::id() as i32, "NOPE"),
+            Err("NOPE: invalid signal specification".to_string())
+        );
+    }
+}
@@ -0,0 +1,72 @@
+/// Largest edit distance at which a name is still suggested.
+const MAX_DISTANCE: usize = 2;
+
+/// Most names suggested for one typo.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Levenshtein distance between `a` and `b`, the number of single-character
+/// insertions, deletions and substitutions that turn one into the other,
+/// except that swapping two adjacent characters also counts as one edit, so
+/// `gti` is as close to `git` as `gt` is.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows for the prefixes of `a` two, one and zero characters shorter.
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 0..a.len() {
+        let mut current = vec![i + 1];
+        for j in 0..b.len() {
+            let substitution = previous[j] + usize::from(a[i] != b[j]);
+            let mut distance = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && a[i] == b[j - 1] && a[i - 1] == b[j] {
+                distance = distance.min(before[j - 1] + 1);
+            }
+            current.push(distance);
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+
+    previous[b.len()]
+}
+
+/// The `candidates` closest to `name`, for "did you mean" hints: at most
+/// three, each within two edits, nearest first and then by name.
+pub fn similar<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let length = name.chars().count();
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.chars().count().abs_diff(length) <= MAX_DISTANCE)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance > 0 && distance <= MAX_DISTANCE)
+        .collect();
+
+    close.sort();
+    close.dedup();
+    close.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("cargo", "cargo"), 0);
+        assert_eq!(edit_distance("carg", "cargo"), 1);
+        assert_eq!(edit_distance("cagro", "cargo"), 1);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("", "ls"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_similar() {
+        let commands = ["cargo", "cat", "cart", "git", "grep", "cargo", "rustc"];
+        assert_eq!(similar("carg", commands), vec!["cargo", "cart", "cat"]);
+        assert_eq!(similar("gti", commands), vec!["git"]);
+        assert_eq!(similar("rustup", commands), vec!["rustc"]);
+        assert!(similar("xyzzy", commands).is_empty());
+    }
+}
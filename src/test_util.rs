@@ -0,0 +1,41 @@
+//! Helpers shared by the tests of several modules.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A scratch directory, `rshell-NAME-PID` under the system temp directory.
+/// It is removed when dropped, so a failing assertion doesn't leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create the directory, clearing out one left by an earlier run.
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("rshell-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
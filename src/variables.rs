@@ -1,5 +1,122 @@
 use std::env;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+/// When the shell started, for `$SECONDS`.
+static START: OnceLock<Instant> = OnceLock::new();
+/// Line of the script or session being run, for `$LINENO`.
+static LINE_NUMBER: AtomicUsize = AtomicUsize::new(0);
+/// State of the `$RANDOM` generator; zero until first seeded.
+static RANDOM_STATE: AtomicU32 = AtomicU32::new(0);
+/// Exit status of the last command, for `$?` and `&&`/`||`.
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Start the `$SECONDS` clock. Called once when the shell starts.
+pub fn start_clock() {
+    START.get_or_init(Instant::now);
+}
+
+/// Set the line number `$LINENO` expands to.
+pub fn set_line_number(line: usize) {
+    LINE_NUMBER.store(line, Ordering::Relaxed);
+}
+
+pub fn line_number() -> usize {
+    LINE_NUMBER.load(Ordering::Relaxed)
+}
+
+/// Record the exit status `$?` expands to.
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+/// Whole seconds elapsed since `start`.
+fn seconds_since(start: Instant) -> u64 {
+    start.elapsed().as_secs()
+}
+
+/// Next `$RANDOM` value, 0 to 32767. An xorshift generator seeded from the
+/// clock and pid is plenty for scripts.
+fn next_random() -> u32 {
+    let mut x = RANDOM_STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        x = (nanos ^ std::process::id().rotate_left(16)) | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    RANDOM_STATE.store(x, Ordering::Relaxed);
+    (x >> 8) & 0x7fff
+}
+
+/// Whether `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a `NAME=value` word into its name and value.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| is_valid_name(name))
+}
+
+/// Read the variable name following a `$`, either `NAME` or `{NAME}`, or the
+/// special `?`. Nothing is consumed when no name follows.
+pub fn take_name(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut lookahead = chars.clone();
+    let braced = lookahead.peek() == Some(&'{');
+    if braced {
+        lookahead.next();
+    }
+
+    let mut name = String::new();
+    if lookahead.peek() == Some(&'?') {
+        name.push('?');
+        lookahead.next();
+    } else {
+        while let Some(&c) = lookahead.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                name.push(c);
+                lookahead.next();
+            } else {
+                break;
+            }
+        }
+    }
+    if braced && lookahead.next() != Some('}') {
+        return None;
+    }
+    if name != "?" && !is_valid_name(&name) {
+        return None;
+    }
+
+    *chars = lookahead;
+    Some(name)
+}
+
+/// Value of a variable; unset variables expand to nothing. `?`, `RANDOM`,
+/// `SECONDS` and `LINENO` are computed by the shell on each reference.
+pub fn lookup(name: &str) -> String {
+    match name {
+        "?" => last_status().to_string(),
+        "RANDOM" => next_random().to_string(),
+        "SECONDS" => seconds_since(*START.get_or_init(Instant::now)).to_string(),
+        "LINENO" => line_number().to_string(),
+        _ => env::var(name).unwrap_or_default(),
+    }
+}
+
+/// Replace each `$NAME` and `${NAME}` in `input` with its value. A `$` not
+/// followed by a name is kept as is.
 #[allow(dead_code)]
 pub fn expand_variables(input: &str) -> String {
     let mut result = String::new();
@@ -7,34 +124,35 @@ pub fn expand_variables(input: &str) -> String {
 
     while let Some(ch) = chars.next() {
         if ch == '$' {
-
-            let mut var_name = String::new();
-            while let Some(&next_ch) = chars.peek() {
-                if next_ch.is_alphanumeric() || next_ch == '_' {
-                    var_name.push(chars.next().unwrap());
-                } else {
-                    break;
-                }
-            }
-
-            if !var_name.is_empty() {
-                if let Ok(value) = env::var(&var_name) {
-                    result.push_str(&value);
-                } else {
-                    result.push('$');
-                    result.push_str(&var_name);
-                }
-            } else {
-                result.push('>');
+            if let Some(name) = take_name(&mut chars) {
+                result.push_str(&lookup(&name));
+                continue;
             }
-        } else {
-            result.push(ch);
         }
+        result.push(ch);
     }
 
     result
 }
 
+/// Backslash-escape an expanded value so the word splitter takes it literally.
+/// `quote` is the quote the expansion appeared in. Unquoted values are still
+/// split on spaces unless `split` is false.
+pub fn escape_value(value: &str, quote: Option<char>, split: bool) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        let special = match quote {
+            Some(_) => c == '"' || c == '\\',
+            None => c == '"' || c == '\'' || c == '\\' || (c == ' ' && !split),
+        };
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,4 +162,46 @@ mod tests {
         std::env::set_var("TEST", "value");
         assert_eq!(expand_variables("echo $TEST"), "echo value");
     }
+
+    #[test]
+    fn test_take_name() {
+        let mut chars = "HOME/x".chars().peekable();
+        assert_eq!(take_name(&mut chars).as_deref(), Some("HOME"));
+        assert_eq!(chars.collect::<String>(), "/x");
+
+        let mut chars = "{USER}s".chars().peekable();
+        assert_eq!(take_name(&mut chars).as_deref(), Some("USER"));
+        assert_eq!(chars.collect::<String>(), "s");
+
+        let mut chars = "{USER".chars().peekable();
+        assert_eq!(take_name(&mut chars), None);
+        assert_eq!(chars.collect::<String>(), "{USER");
+
+        let mut chars = "?x".chars().peekable();
+        assert_eq!(take_name(&mut chars).as_deref(), Some("?"));
+        assert_eq!(chars.collect::<String>(), "x");
+    }
+
+    #[test]
+    fn test_split_assignment() {
+        assert_eq!(split_assignment("X=a b"), Some(("X", "a b")));
+        assert_eq!(split_assignment("1X=a"), None);
+        assert_eq!(split_assignment("--flag=a"), None);
+    }
+
+    #[test]
+    fn test_random_varies() {
+        let values: Vec<String> = (0..8).map(|_| lookup("RANDOM")).collect();
+        assert!(values.iter().all(|v| v.parse::<u32>().is_ok_and(|n| n <= 32767)));
+        assert!(values.iter().any(|v| *v != values[0]));
+    }
+
+    #[test]
+    fn test_seconds_increases() {
+        let start = Instant::now();
+        let earlier = start.checked_sub(std::time::Duration::from_secs(5)).unwrap();
+        assert_eq!(seconds_since(start), 0);
+        assert!(seconds_since(earlier) >= 5);
+        assert!(lookup("SECONDS").parse::<u64>().is_ok());
+    }
 }